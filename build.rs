@@ -0,0 +1,22 @@
+//! Fits the table of smooth spectra for RGB colors, which spectral renders
+//! read in with `include_bytes!` instead of fitting it on every start.
+
+use std::{env, fs, path::Path};
+
+// the crate uses the rest of it
+#[allow(dead_code)]
+#[path = "src/rgb_to_spectrum.rs"]
+mod rgb_to_spectrum;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/rgb_to_spectrum.rs");
+    let bytes: Vec<u8> = rgb_to_spectrum::fit()
+        .iter()
+        .flatten()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    let out_dir = env::var("OUT_DIR").expect("cargo sets OUT_DIR");
+    fs::write(Path::new(&out_dir).join("rgb_to_spectrum.bin"), bytes)
+        .expect("couldn't write the RGB to spectrum table");
+}
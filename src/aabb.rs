@@ -0,0 +1,129 @@
+use crate::{interval::Interval, ray::Ray, vec3::Vec3};
+
+/// Axis-aligned bounding box, stored as one interval per axis.
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Aabb {
+    /// An empty box; the identity for `union`.
+    pub fn new() -> Self {
+        Self {
+            x: Interval::new(),
+            y: Interval::new(),
+            z: Interval::new(),
+        }
+    }
+    pub fn new_with_intervals(x: Interval, y: Interval, z: Interval) -> Self {
+        let mut bbox = Self { x, y, z };
+        bbox.pad_to_minimums();
+        bbox
+    }
+    pub fn new_from_points(a: &Vec3, b: &Vec3) -> Self {
+        Self::new_with_intervals(
+            Interval::new_with_values(a.x.min(b.x), a.x.max(b.x)),
+            Interval::new_with_values(a.y.min(b.y), a.y.max(b.y)),
+            Interval::new_with_values(a.z.min(b.z), a.z.max(b.z)),
+        )
+    }
+    pub fn union(a: &Aabb, b: &Aabb) -> Self {
+        Self {
+            x: Interval::new_enclosing(&a.x, &b.x),
+            y: Interval::new_enclosing(&a.y, &b.y),
+            z: Interval::new_enclosing(&a.z, &b.z),
+        }
+    }
+
+    pub fn axis_interval(&self, n: usize) -> &Interval {
+        match n {
+            1 => &self.y,
+            2 => &self.z,
+            _ => &self.x,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.size() < 0.0 || self.y.size() < 0.0 || self.z.size() < 0.0
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        if self.x.size() > self.y.size() {
+            if self.x.size() > self.z.size() {
+                0
+            } else {
+                2
+            }
+        } else if self.y.size() > self.z.size() {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        Vec3::new(
+            0.5 * (self.x.min + self.x.max),
+            0.5 * (self.y.min + self.y.max),
+            0.5 * (self.z.min + self.z.max),
+        )
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let dx = self.x.size();
+        let dy = self.y.size();
+        let dz = self.z.size();
+        2.0 * (dx * dy + dy * dz + dz * dx)
+    }
+
+    /// Slab test: shrinks `interval` against each axis and reports whether
+    /// anything is left.
+    pub fn hit(&self, ray: &Ray, interval: &Interval) -> bool {
+        let mut t_min = interval.min;
+        let mut t_max = interval.max;
+        for axis in 0..3 {
+            let ax = self.axis_interval(axis);
+            let adinv = 1.0 / ray.direction.axis(axis);
+            let origin = ray.origin.axis(axis);
+
+            let t0 = (ax.min - origin) * adinv;
+            let t1 = (ax.max - origin) * adinv;
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            if t0 > t_min {
+                t_min = t0;
+            }
+            if t1 < t_max {
+                t_max = t1;
+            }
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
+    }
+
+    // flat primitives would otherwise produce zero-width slabs
+    fn pad_to_minimums(&mut self) {
+        const DELTA: f64 = 0.0001;
+        if self.x.size() < DELTA {
+            self.x = self.x.expand(DELTA);
+        }
+        if self.y.size() < DELTA {
+            self.y = self.y.expand(DELTA);
+        }
+        if self.z.size() < DELTA {
+            self.z = self.z.expand(DELTA);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use crate::sampler::{self, Sampler};
+use std::{f64::consts::PI, path::Path, sync::Arc};
+
+/// Shape of the lens opening, which is also the shape out-of-focus
+/// highlights take. Points are sampled in lens coordinates, where the
+/// opening fits in the unit disk.
+#[derive(Clone)]
+pub enum Aperture {
+    Circle,
+    /// A regular polygon, as formed by straight diaphragm blades.
+    Polygon(PolygonAperture),
+    /// A grayscale image; brighter pixels let more light through.
+    Mask(Arc<ApertureMask>),
+}
+
+impl Aperture {
+    /// A uniformly distributed point on the opening, or weighted by
+    /// transmission for masks.
+    pub fn sample(&self, sampler: &mut dyn Sampler) -> (f64, f64) {
+        match self {
+            Aperture::Circle => sampler::sample_disk(sampler.next_2d()),
+            Aperture::Polygon(polygon) => {
+                // all triangles between the center and an edge have the same
+                // area, so pick one and sample it uniformly
+                let blades = polygon.blades;
+                let step = 2.0 * PI / blades as f64;
+                let blade = ((sampler.next_1d() * blades as f64) as usize).min(blades - 1);
+                let start = polygon.rotation.to_radians() + step * blade as f64;
+                let (mut a, mut b) = sampler.next_2d();
+                if a + b > 1.0 {
+                    (a, b) = (1.0 - a, 1.0 - b);
+                }
+                let end = start + step;
+                (
+                    a * start.cos() + b * end.cos(),
+                    a * start.sin() + b * end.sin(),
+                )
+            }
+            Aperture::Mask(mask) => mask.sample(sampler),
+        }
+    }
+}
+
+/// The opening left by `blades` straight blades, turned by `rotation`
+/// degrees.
+#[derive(Clone, Copy, Debug)]
+pub struct PolygonAperture {
+    blades: usize,
+    rotation: f64,
+}
+
+impl PolygonAperture {
+    pub fn new(blades: usize, rotation: f64) -> Result<Self, String> {
+        if blades < 3 {
+            return Err("an aperture needs at least 3 blades".to_string());
+        }
+        Ok(Self { blades, rotation })
+    }
+
+    pub fn blades(&self) -> usize {
+        self.blades
+    }
+
+    pub fn rotation(&self) -> f64 {
+        self.rotation
+    }
+}
+
+/// An image used as the aperture, stretched over `[-1, 1]` along its longer
+/// side and sampled in proportion to its brightness.
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    /// Running sum of pixel brightness, normalized so the last entry is 1.
+    cdf: Vec<f64>,
+}
+
+impl ApertureMask {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let image = image::open(path).map_err(|err| err.to_string())?.to_luma8();
+        let (width, height) = image.dimensions();
+        let mut total = 0.0;
+        let mut cdf: Vec<f64> = image
+            .pixels()
+            .map(|pixel| {
+                total += pixel[0] as f64;
+                total
+            })
+            .collect();
+        if total <= 0.0 {
+            return Err("the image is completely black".to_string());
+        }
+        for value in cdf.iter_mut() {
+            *value /= total;
+        }
+        Ok(Self {
+            width: width as usize,
+            height: height as usize,
+            cdf,
+        })
+    }
+
+    fn sample(&self, sampler: &mut dyn Sampler) -> (f64, f64) {
+        let target = sampler.next_1d();
+        let index = self
+            .cdf
+            .partition_point(|&value| value <= target)
+            .min(self.cdf.len() - 1);
+        let (i, j) = (index % self.width, index / self.width);
+        let size = self.width.max(self.height) as f64;
+        let (jitter_x, jitter_y) = sampler.next_2d();
+        let x = (i as f64 + jitter_x - 0.5 * self.width as f64) * 2.0 / size;
+        // image rows run top to bottom, lens v runs up
+        let y = (0.5 * self.height as f64 - j as f64 - jitter_y) * 2.0 / size;
+        (x, y)
+    }
+}
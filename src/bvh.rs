@@ -0,0 +1,248 @@
+use crate::{
+    aabb::Aabb,
+    hit_record::{HitRecord, Hittable},
+    hittable_list::HittableList,
+    interval::Interval,
+    ray::Ray,
+    vec3::Vec3,
+};
+
+// Binned SAH: centroids are bucketed along an axis and every bucket boundary
+// is evaluated as a candidate split.
+const BIN_COUNT: usize = 16;
+// Leaves bigger than this are always split, whatever the SAH says.
+const MAX_LEAF_SIZE: usize = 4;
+// Cost of visiting a node relative to one primitive intersection.
+const TRAVERSAL_COST: f64 = 0.125;
+
+struct BuildItem {
+    bbox: Aabb,
+    centroid: Vec3,
+    object: Box<dyn Hittable>,
+}
+
+pub struct BvhNode {
+    left: Box<dyn Hittable>,
+    right: Box<dyn Hittable>,
+    bbox: Aabb,
+}
+
+impl BvhNode {
+    /// Builds a bounding volume hierarchy over `objects` using the surface
+    /// area heuristic. Small groups that are not worth splitting end up in a
+    /// `HittableList` leaf, and a single object is returned as-is.
+    pub fn build(objects: Vec<Box<dyn Hittable>>) -> Box<dyn Hittable> {
+        let items = objects
+            .into_iter()
+            .map(|object| {
+                let bbox = object.bounding_box();
+                BuildItem {
+                    bbox,
+                    centroid: bbox.centroid(),
+                    object,
+                }
+            })
+            .collect();
+        Self::build_node(items)
+    }
+
+    pub fn from_list(list: HittableList) -> Box<dyn Hittable> {
+        Self::build(list.objects)
+    }
+
+    fn build_node(mut items: Vec<BuildItem>) -> Box<dyn Hittable> {
+        match items.len() {
+            0 => return Box::new(HittableList::new()),
+            1 => return items.pop().unwrap().object,
+            _ => {}
+        }
+
+        let bbox = items
+            .iter()
+            .fold(Aabb::new(), |acc, item| Aabb::union(&acc, &item.bbox));
+        let centroid_bounds = items.iter().fold(Aabb::new(), |acc, item| {
+            Aabb::union(&acc, &Aabb::new_from_points(&item.centroid, &item.centroid))
+        });
+
+        let (left, right) = match Self::find_split(&items, &bbox, &centroid_bounds) {
+            Some((cost, _, _)) if items.len() <= MAX_LEAF_SIZE && cost >= items.len() as f64 => {
+                return Self::leaf(items)
+            }
+            Some((_, axis, split)) => {
+                let extent = *centroid_bounds.axis_interval(axis);
+                items
+                    .into_iter()
+                    .partition(|item| Self::bin_index(item.centroid.axis(axis), &extent) < split)
+            }
+            None => {
+                // every centroid falls in the same bin, so split by count instead
+                let axis = bbox.longest_axis();
+                items.sort_by(|a, b| a.centroid.axis(axis).total_cmp(&b.centroid.axis(axis)));
+                let right = items.split_off(items.len() / 2);
+                (items, right)
+            }
+        };
+
+        Box::new(Self {
+            left: Self::build_node(left),
+            right: Self::build_node(right),
+            bbox,
+        })
+    }
+
+    /// Returns the cheapest `(cost, axis, bin)` split, where objects in bins
+    /// below `bin` go left. Cost is relative to intersecting one primitive.
+    fn find_split(
+        items: &[BuildItem],
+        bbox: &Aabb,
+        centroid_bounds: &Aabb,
+    ) -> Option<(f64, usize, usize)> {
+        let parent_area = bbox.surface_area().max(f64::MIN_POSITIVE);
+        let mut best: Option<(f64, usize, usize)> = None;
+
+        for axis in 0..3 {
+            let extent = centroid_bounds.axis_interval(axis);
+            let mut bins = [(Aabb::new(), 0usize); BIN_COUNT];
+            for item in items {
+                let bin = &mut bins[Self::bin_index(item.centroid.axis(axis), extent)];
+                bin.0 = Aabb::union(&bin.0, &item.bbox);
+                bin.1 += 1;
+            }
+
+            // sweep from the right so each split is O(1) to evaluate
+            let mut right_area = [0.0; BIN_COUNT];
+            let mut right_count = [0usize; BIN_COUNT];
+            let mut acc = Aabb::new();
+            let mut count = 0;
+            for i in (1..BIN_COUNT).rev() {
+                acc = Aabb::union(&acc, &bins[i].0);
+                count += bins[i].1;
+                right_area[i] = acc.surface_area();
+                right_count[i] = count;
+            }
+
+            let mut acc = Aabb::new();
+            let mut count = 0;
+            for split in 1..BIN_COUNT {
+                acc = Aabb::union(&acc, &bins[split - 1].0);
+                count += bins[split - 1].1;
+                if count == 0 || right_count[split] == 0 {
+                    continue;
+                }
+                let cost = TRAVERSAL_COST
+                    + (acc.surface_area() * count as f64
+                        + right_area[split] * right_count[split] as f64)
+                        / parent_area;
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+        best
+    }
+
+    fn bin_index(value: f64, extent: &Interval) -> usize {
+        let size = extent.size();
+        if size <= 0.0 {
+            return 0;
+        }
+        let bin = ((value - extent.min) / size * BIN_COUNT as f64) as usize;
+        bin.min(BIN_COUNT - 1)
+    }
+
+    fn leaf(items: Vec<BuildItem>) -> Box<dyn Hittable> {
+        let mut list = HittableList::new();
+        for item in items {
+            list.push(item.object);
+        }
+        Box::new(list)
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit<'a>(&'a self, ray: &Ray, interval: &Interval, hit_record: &mut HitRecord<'a>) -> bool {
+        if !self.bbox.hit(ray, interval) {
+            return false;
+        }
+        let hit_left = self.left.hit(ray, interval, hit_record);
+        let closest = if hit_left { hit_record.t } else { interval.max };
+        let hit_right = self.right.hit(
+            ray,
+            &Interval::new_with_values(interval.min, closest),
+            hit_record,
+        );
+        hit_left || hit_right
+    }
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Material, sphere::Sphere, texture::Texture, triangle::Triangle};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Spheres and triangles scattered through a cube, each with a material
+    /// whose red channel is its index so hits can be told apart.
+    fn scattered_objects() -> Vec<Box<dyn Hittable>> {
+        let mut rng = StdRng::seed_from_u64(1);
+        (0..400)
+            .map(|index| {
+                let material = Material::Lambertian {
+                    albedo: Texture::Solid(Vec3::new(index as f64, 0.0, 0.0)),
+                };
+                let center = Vec3::new_rand_ranged(&mut rng, -10.0, 10.0);
+                let object: Box<dyn Hittable> = if index % 2 == 0 {
+                    let radius = 0.1 + Vec3::new_rand(&mut rng).x;
+                    Box::new(Sphere::new(&center, radius, material))
+                } else {
+                    let corner = |rng: &mut StdRng| {
+                        Vec3::add(&center, &Vec3::new_rand_ranged(rng, -1.5, 1.5))
+                    };
+                    let (a, b, c) = (corner(&mut rng), corner(&mut rng), corner(&mut rng));
+                    Box::new(Triangle::new(&a, &b, &c, material))
+                };
+                object
+            })
+            .collect()
+    }
+
+    fn object_index(hit_record: &HitRecord) -> usize {
+        match hit_record.material {
+            Some(Material::Lambertian {
+                albedo: Texture::Solid(color),
+            }) => color.x as usize,
+            _ => panic!("hit without the test material"),
+        }
+    }
+
+    #[test]
+    fn finds_the_same_closest_hit_as_a_list() {
+        let mut list = HittableList::new();
+        for object in scattered_objects() {
+            list.push(object);
+        }
+        let bvh = BvhNode::build(scattered_objects());
+        let mut rng = StdRng::seed_from_u64(2);
+        let interval = Interval::new_with_values(0.001, f64::INFINITY);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            // from around the cube towards a point inside it
+            let origin = Vec3::new_rand_ranged(&mut rng, -15.0, 15.0);
+            let target = Vec3::new_rand_ranged(&mut rng, -10.0, 10.0);
+            let ray = Ray::new(&origin, &Vec3::unit(&Vec3::sub(&target, &origin)));
+            let (mut expected, mut actual) = (HitRecord::new(), HitRecord::new());
+            let hit = list.hit(&ray, &interval, &mut expected);
+            assert_eq!(hit, bvh.hit(&ray, &interval, &mut actual));
+            if hit {
+                hits += 1;
+                assert_eq!(expected.t, actual.t);
+                assert_eq!(object_index(&expected), object_index(&actual));
+            }
+        }
+        // enough rays have to hit something for the comparison to count
+        assert!(hits > 500, "only {hits} rays hit");
+    }
+}
@@ -1,13 +1,50 @@
 use crate::{
+    aperture::Aperture,
+    framebuffer::{Framebuffer, PixelStats},
     hit_record::{HitRecord, Hittable},
-    hittable_list::HittableList,
     interval::Interval,
+    light::LightList,
+    material::Material,
+    medium::{Fog, Medium},
     ray::Ray,
+    render::{ColorMode, RenderSettings},
+    sampler::Sampler,
+    spectrum::{Illuminant, Wavelengths},
+    tiles::RenderHooks,
     vec3::Vec3,
 };
-use rand::Rng;
 use rayon::prelude::*;
-use std::{f64::consts::PI, sync::Arc};
+use std::f64::consts::PI;
+
+/// What a ray sees when it leaves the scene without hitting anything.
+#[derive(Clone, Copy)]
+pub enum Background {
+    /// The white-to-blue gradient from the book.
+    Sky,
+    /// A constant color; black turns the sky off so only lights illuminate
+    /// the scene.
+    Color(Vec3),
+}
+impl Background {
+    /// Radiance along `ray`, in its colors.
+    pub fn color(&self, ray: &Ray) -> Vec3 {
+        let rgb = match self {
+            Background::Sky => {
+                let unit_dir = Vec3::unit(&ray.direction);
+                let t = 0.5 * (unit_dir.y + 1.0);
+                Vec3::add(
+                    &Vec3::mul(&Vec3::new(1.0, 1.0, 1.0), 1.0 - t),
+                    &Vec3::mul(&Vec3::new(0.5, 0.7, 1.0), t),
+                )
+            }
+            Background::Color(color) => *color,
+        };
+        match ray.wavelengths {
+            Some(wavelengths) => wavelengths.emission(&rgb, &Illuminant::D65),
+            None => rgb,
+        }
+    }
+}
 
 pub struct Camera {
     pub aspect_ratio: f64,
@@ -17,27 +54,145 @@ pub struct Camera {
     pub pixel00_loc: Vec3,
     pub pixel_delta_u: Vec3,
     pub pixel_delta_v: Vec3,
-    samples_per_pixel: usize,
-    max_depth: usize,
+    pub background: Background,
+    pub aperture: Aperture,
+    /// How far off-axis pixels see the aperture clipped by the lens barrel,
+    /// turning round bokeh into cat's eyes towards the image corners and
+    /// darkening them. 0 disables it; 1 shifts the clipping circle by the
+    /// aperture radius at the corners.
+    pub cat_eye: f64,
+    pub fog: Option<Fog>,
     fov: f64,
+    look_at: Vec3,
+    focus_dist: f64,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
     defocus_angle: f64,
 }
 
-impl Camera {
-    pub fn new(
-        aspect_ratio: f64,
-        image_width: usize,
-        lookfrom: Vec3,
-        lookat: Vec3,
-        fov: f64,
-        defocus_angle: f64,
-        focus_dist: f64,
-    ) -> Self {
+/// Sets up a `Camera`. Everything has a default, so only what differs
+/// needs to be given, e.g.
+/// `Camera::builder().image_size(800, 600).look_from(from).look_at(to).build()`.
+#[derive(Clone)]
+pub struct CameraBuilder {
+    image_width: usize,
+    aspect_ratio: f64,
+    look_from: Vec3,
+    look_at: Vec3,
+    fov: f64,
+    defocus_angle: f64,
+    focus_dist: Option<f64>,
+    aperture: Aperture,
+    cat_eye: f64,
+    background: Background,
+    fog: Option<Fog>,
+}
+
+impl Default for CameraBuilder {
+    fn default() -> Self {
+        Self {
+            image_width: 400,
+            aspect_ratio: 16.0 / 9.0,
+            look_from: Vec3::new(0.0, 0.0, 0.0),
+            look_at: Vec3::new(0.0, 0.0, -1.0),
+            fov: 90.0,
+            defocus_angle: 0.0,
+            focus_dist: None,
+            aperture: Aperture::Circle,
+            cat_eye: 0.0,
+            background: Background::Sky,
+            fog: None,
+        }
+    }
+}
+
+impl CameraBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Width in pixels; the height follows from the aspect ratio.
+    pub fn image_width(mut self, image_width: usize) -> Self {
+        self.image_width = image_width;
+        self
+    }
+
+    /// Width over height of the image.
+    pub fn aspect_ratio(mut self, aspect_ratio: f64) -> Self {
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+
+    pub fn image_size(self, width: usize, height: usize) -> Self {
+        self.image_width(width)
+            .aspect_ratio(width as f64 / height.max(1) as f64)
+    }
+
+    pub fn look_from(mut self, look_from: Vec3) -> Self {
+        self.look_from = look_from;
+        self
+    }
+
+    /// Must differ from `look_from`.
+    pub fn look_at(mut self, look_at: Vec3) -> Self {
+        self.look_at = look_at;
+        self
+    }
+
+    /// Vertical field of view in degrees.
+    pub fn fov(mut self, fov: f64) -> Self {
+        self.fov = fov;
+        self
+    }
+
+    /// Angle of the cone of rays through each pixel, in degrees; 0 keeps
+    /// everything in focus.
+    pub fn defocus_angle(mut self, defocus_angle: f64) -> Self {
+        self.defocus_angle = defocus_angle;
+        self
+    }
+
+    /// Distance to the plane in perfect focus. Defaults to the distance
+    /// between `look_from` and `look_at`.
+    pub fn focus_dist(mut self, focus_dist: f64) -> Self {
+        self.focus_dist = Some(focus_dist);
+        self
+    }
+
+    pub fn aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    /// See `Camera::cat_eye`.
+    pub fn cat_eye(mut self, cat_eye: f64) -> Self {
+        self.cat_eye = cat_eye;
+        self
+    }
+
+    pub fn background(mut self, background: Background) -> Self {
+        self.background = background;
+        self
+    }
+
+    pub fn fog(mut self, fog: Fog) -> Self {
+        self.fog = Some(fog);
+        self
+    }
+
+    pub fn build(&self) -> Camera {
+        let aspect_ratio = self.aspect_ratio;
+        let image_width = self.image_width;
+        let lookfrom = self.look_from;
+        let lookat = self.look_at;
+        let fov = self.fov;
+        let defocus_angle = self.defocus_angle;
+        let focus_dist = self
+            .focus_dist
+            .unwrap_or_else(|| Vec3::sub(&lookfrom, &lookat).length());
+
         let image_height = image_width as f64 / aspect_ratio;
-        let image_height = image_height.max(1.0) as usize;
-        let def_ang = 0.;
+        let image_height = image_height.round().max(1.0) as usize;
         let vup = Vec3::new(0., 1.0, 0.);
 
         let theta = fov * PI / 180.;
@@ -60,11 +215,7 @@ impl Camera {
             &Vec3::sub(&center, &Vec3::mul(&w, focus_dist)),
             &Vec3::add(&Vec3::div(&viewport_u, 2.0), &Vec3::div(&viewport_v, 2.0)),
         );
-        // let viewport_upper_left = Vec3::sub(
-        //     &Vec3::sub(&center, &Vec3::new(0.0, 0.0, focal_length)),
-        //     &Vec3::add(&Vec3::div(&viewport_u, 2.0), &Vec3::div(&viewport_v, 2.0)),
-        // );
-        let defocus_radius = ((def_ang / 2.) * PI / 180.).tan() * focus_dist;
+        let defocus_radius = ((defocus_angle / 2.) * PI / 180.).tan() * focus_dist;
 
         let defocus_disk_u = Vec3::mul(&u, defocus_radius);
         let defocus_disk_v = Vec3::mul(&v, defocus_radius);
@@ -74,7 +225,7 @@ impl Camera {
             &Vec3::mul(&Vec3::add(&pixel_delta_u, &pixel_delta_v), 0.5),
         );
 
-        Self {
+        Camera {
             aspect_ratio,
             image_width,
             image_height,
@@ -82,95 +233,413 @@ impl Camera {
             pixel00_loc,
             pixel_delta_u,
             pixel_delta_v,
-            samples_per_pixel: 100,
-            max_depth: 50,
+            background: self.background,
+            aperture: self.aperture.clone(),
+            cat_eye: self.cat_eye,
+            fog: self.fog,
             fov,
+            look_at: lookat,
+            focus_dist,
             defocus_disk_u,
             defocus_disk_v,
             defocus_angle,
         }
     }
-    fn linear_to_gamma(linear: f64) -> f64 {
-        if linear > 0.0 {
-            return linear.sqrt();
-        }
-        0.0
-    }
+}
 
-    pub fn process_ray(&self, i: usize, j: usize, world: &HittableList) -> Vec3 {
-        let mut tmp_color = Vec3::default();
-        for _ in 0..self.samples_per_pixel {
-            let ray = self.get_ray(i, j);
+impl Camera {
+    pub fn builder() -> CameraBuilder {
+        CameraBuilder::new()
+    }
 
-            let color = Self::ray_color(&ray, self.max_depth, world);
-            tmp_color = Vec3::add(&tmp_color, &color);
+    /// The same camera looking from somewhere else, keeping the image size,
+    /// aperture, background and fog.
+    pub fn with_view(
+        &self,
+        lookfrom: Vec3,
+        lookat: Vec3,
+        fov: f64,
+        defocus_angle: f64,
+        focus_dist: f64,
+    ) -> Self {
+        let mut builder = Self::builder()
+            .image_width(self.image_width)
+            .aspect_ratio(self.aspect_ratio)
+            .look_from(lookfrom)
+            .look_at(lookat)
+            .fov(fov)
+            .defocus_angle(defocus_angle)
+            .focus_dist(focus_dist)
+            .aperture(self.aperture.clone())
+            .cat_eye(self.cat_eye)
+            .background(self.background);
+        builder.fog = self.fog;
+        builder.build()
+    }
+    pub fn look_at(&self) -> Vec3 {
+        self.look_at
+    }
+    pub fn fov(&self) -> f64 {
+        self.fov
+    }
+    pub fn defocus_angle(&self) -> f64 {
+        self.defocus_angle
+    }
+    pub fn focus_dist(&self) -> f64 {
+        self.focus_dist
+    }
+    /// Adds paths through pixel `(i, j)` to `stats` until it holds
+    /// `samples_per_pixel` of them, or fewer if adaptive sampling finds the
+    /// pixel converged. Samples already in `stats` are kept, so a pixel can
+    /// be continued later with the same result as rendering it in one go.
+    pub fn process_ray(
+        &self,
+        i: usize,
+        j: usize,
+        world: &dyn Hittable,
+        lights: &LightList,
+        settings: &RenderSettings,
+        stats: &mut PixelStats,
+    ) {
+        let mut sampler = settings.sampler.create(settings.seed);
+        while stats.count < settings.samples_per_pixel
+            && !settings
+                .adaptive
+                .is_some_and(|adaptive| adaptive.converged(stats))
+        {
+            sampler.start_sample(i, j, stats.count);
+            let color = self.sample_pixel(i, j, world, lights, settings, sampler.as_mut());
+            stats.add(&color);
         }
-        tmp_color = Vec3::div(&tmp_color, self.samples_per_pixel as f64);
-        Vec3::new(
-            Self::linear_to_gamma(tmp_color.x),
-            Self::linear_to_gamma(tmp_color.y),
-            Self::linear_to_gamma(tmp_color.z),
-        )
     }
 
-    pub fn render(camera: Arc<Camera>, world: HittableList) -> Vec<Vec<Vec3>> {
-        // let mut thread_handles = vec![];
+    /// Radiance along one random path through pixel `(i, j)`.
+    pub fn sample_pixel(
+        &self,
+        i: usize,
+        j: usize,
+        world: &dyn Hittable,
+        lights: &LightList,
+        settings: &RenderSettings,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        let Some(mut ray) = self.get_ray(i, j, sampler) else {
+            return Vec3::default();
+        };
+        match settings.color_mode {
+            ColorMode::Rgb => self.ray_color(&ray, settings.max_depth, world, lights, sampler),
+            ColorMode::Spectral => {
+                let wavelengths = Wavelengths::sample(sampler.next_1d());
+                ray.wavelengths = Some(wavelengths);
+                let radiance = self.ray_color(&ray, settings.max_depth, world, lights, sampler);
+                wavelengths.to_rgb(&radiance)
+            }
+        }
+    }
 
-        (0..camera.image_height)
+    /// Sample `pass` of every pixel, for progressive rendering: averaging
+    /// passes `0..n` gives the same image as rendering `n` spp. Pixels are
+    /// stored row by row from the top.
+    pub fn render_pass(
+        &self,
+        world: &dyn Hittable,
+        lights: &LightList,
+        settings: &RenderSettings,
+        pass: usize,
+    ) -> Vec<Vec3> {
+        (0..self.image_width * self.image_height)
             .into_par_iter()
-            .map(|j| {
-                (0..camera.image_width)
-                    .into_par_iter()
-                    .map(|i| camera.process_ray(i, j, &world))
-                    .collect::<Vec<Vec3>>()
+            .map(|index| {
+                let (i, j) = (index % self.image_width, index / self.image_width);
+                let mut sampler = settings.sampler.create(settings.seed);
+                sampler.start_sample(i, j, pass);
+                self.sample_pixel(i, j, world, lights, settings, sampler.as_mut())
             })
-            .collect::<Vec<Vec<Vec3>>>()
+            .collect()
+    }
+
+    /// Renders the image tile by tile as `settings.tiles` says, adding
+    /// samples to `framebuffer` until every pixel is done. Pass a fresh
+    /// framebuffer to start from scratch or a checkpoint to continue one.
+    pub fn render(
+        &self,
+        world: &dyn Hittable,
+        lights: &LightList,
+        settings: &RenderSettings,
+        framebuffer: Framebuffer,
+        hooks: &RenderHooks,
+    ) -> Framebuffer {
+        settings.tiles.run(framebuffer, hooks, |i, j, stats| {
+            self.process_ray(i, j, world, lights, settings, stats)
+        })
     }
 
-    pub fn ray_color(ray: &Ray, depth: usize, world: &HittableList) -> Vec3 {
+    pub fn ray_color(
+        &self,
+        ray: &Ray,
+        depth: usize,
+        world: &dyn Hittable,
+        lights: &LightList,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        self.trace(ray, depth, world, lights, sampler, None)
+    }
+
+    /// Path tracing with next-event estimation. At every non-specular hit a
+    /// light is sampled directly, and both that sample and emission found by
+    /// the BSDF-sampled continuation are weighted with the power heuristic.
+    /// `bsdf_pdf` is the density the previous bounce sampled `ray` with, or
+    /// `None` for camera rays and specular bounces, whose emission is counted
+    /// in full since light sampling could not have found it. Inside volumes
+    /// and fog, the ray may run into a particle before the next surface and
+    /// scatter from there by the medium's phase function.
+    fn trace(
+        &self,
+        ray: &Ray,
+        depth: usize,
+        world: &dyn Hittable,
+        lights: &LightList,
+        sampler: &mut dyn Sampler,
+        bsdf_pdf: Option<f64>,
+    ) -> Vec3 {
         if depth == 0 {
             return Vec3::default();
         }
 
-        // Only lock the world for the part where you need to access it
-        let hit_record = {
+        // find the first surface, crossing into and out of volumes on the
+        // way, unless the ray runs into a particle of a medium before
+        let mut medium = ray.medium;
+        let mut t_min = 0.001;
+        let (hit_record, collision) = loop {
             let mut hit_record = HitRecord::new();
-            if world.hit(
+            let hit = world.hit(
                 ray,
-                &Interval::new_with_values(0.001, f64::INFINITY),
+                &Interval::new_with_values(t_min, f64::INFINITY),
                 &mut hit_record,
-            ) {
-                Some(hit_record)
-            } else {
-                None
+            );
+            let t_max = if hit { hit_record.t } else { f64::INFINITY };
+            if let Some(collision) = self.sample_collision(ray, medium, t_min, t_max, sampler) {
+                break (None, Some(collision));
+            }
+            if !hit {
+                break (None, None);
+            }
+            match hit_record.material {
+                Some(Material::Medium(volume)) => {
+                    medium = hit_record.front_face.then_some(*volume);
+                    t_min = hit_record.t + 0.001;
+                }
+                _ => break (Some(hit_record), None),
             }
         };
+        let ray = &Ray { medium, ..*ray };
 
-        if let Some(hit_record) = hit_record {
-            let mut scattered = Ray::new(&Vec3::default(), &Vec3::default());
-            let mut attenuation = Vec3::default();
-            let material = hit_record.material;
-
-            if let Some(mat) = material {
-                if mat.scatter(ray, &hit_record, &mut attenuation, &mut scattered) {
-                    return Vec3::mul_vec(
-                        &attenuation,
-                        &Self::ray_color(&scattered, depth - 1, world),
-                    );
+        // a collision scatters like a surface made of the medium would
+        let scattering: Material;
+        let hit_record = match (hit_record, collision) {
+            (_, Some((t, collided))) => {
+                scattering = Material::Medium(collided);
+                HitRecord {
+                    t,
+                    point: ray.at(t),
+                    material: Some(&scattering),
+                    ..HitRecord::new()
                 }
             }
+            (Some(hit_record), None) => hit_record,
+            (None, None) => return self.background.color(ray),
+        };
+        let Some(mat) = hit_record.material else {
+            return Vec3::default();
+        };
+
+        let mut emitted = mat.emitted(ray, &hit_record);
+        if let Some(bsdf_pdf) = bsdf_pdf {
+            if !emitted.near_zero() {
+                let light_pdf = lights.pdf(&ray.origin, &ray.direction, hit_record.t);
+                emitted = Vec3::mul(&emitted, power_heuristic(bsdf_pdf, light_pdf));
+            }
+        }
+
+        let Some(mut scatter) = mat.scatter(ray, &hit_record, sampler) else {
+            return emitted;
+        };
+        // once dispersion has picked a wavelength the rest of the path
+        // keeps it
+        if scatter.scattered.wavelength.is_none() {
+            scatter.scattered.wavelength = ray.wavelength;
+        }
+        if let Some(wavelengths) = ray.wavelengths {
+            // materials work in RGB, so their colors become spectra here
+            scatter.attenuation = wavelengths.reflectance(&scatter.attenuation);
+            if ray.wavelength.is_none() && scatter.scattered.wavelength.is_some() {
+                // dispersion sent each wavelength its own way, and the path
+                // went the hero's
+                scatter.attenuation = wavelengths.hero_only(&scatter.attenuation);
+            }
+            scatter.scattered.wavelengths = Some(wavelengths);
+        }
+        scatter.scattered.medium = ray.medium;
+        if scatter.is_specular || lights.is_empty() {
+            let next_pdf = if scatter.is_specular {
+                None
+            } else {
+                Some(scatter.pdf)
+            };
+            return Vec3::add(
+                &emitted,
+                &Vec3::mul_vec(
+                    &scatter.attenuation,
+                    &self.trace(
+                        &scatter.scattered,
+                        depth - 1,
+                        world,
+                        lights,
+                        sampler,
+                        next_pdf,
+                    ),
+                ),
+            );
+        }
+
+        let direct = self.sample_direct(ray, &hit_record, mat, world, lights, sampler);
+        let indirect = Vec3::mul_vec(
+            &scatter.attenuation,
+            &self.trace(
+                &scatter.scattered,
+                depth - 1,
+                world,
+                lights,
+                sampler,
+                Some(scatter.pdf),
+            ),
+        );
+        Vec3::add(&emitted, &Vec3::add(&direct, &indirect))
+    }
+
+    fn sample_direct(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        mat: &Material,
+        world: &dyn Hittable,
+        lights: &LightList,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        let Some(sample) = lights.sample(&hit_record.point, ray.wavelengths, sampler) else {
+            return Vec3::default();
+        };
+        let bsdf_pdf = mat.pdf(ray, hit_record, &sample.direction);
+        let mut f = mat.eval(ray, hit_record, &sample.direction);
+        if let Some(wavelengths) = ray.wavelengths {
+            // upsampled the way `trace` does the attenuation `scatter` would
+            // give for this direction, so both strategies see one spectrum
+            f = if bsdf_pdf > 0.0 {
+                Vec3::mul(&wavelengths.reflectance(&Vec3::div(&f, bsdf_pdf)), bsdf_pdf)
+            } else {
+                wavelengths.reflectance(&f)
+            };
+        }
+        if f.near_zero() || sample.emission.near_zero() {
+            return Vec3::default();
+        }
+
+        let transmittance = self.transmittance(
+            world,
+            &hit_record.point,
+            &sample.direction,
+            sample.distance,
+            ray.medium,
+        );
+        if transmittance <= 0.0 {
+            return Vec3::default();
         }
 
-        let unit_dir = Vec3::unit(&ray.direction);
-        let t = 0.5 * (unit_dir.y + 1.0);
-        Vec3::add(
-            &Vec3::mul(&Vec3::new(1.0, 1.0, 1.0), 1.0 - t),
-            &Vec3::mul(&Vec3::new(0.5, 0.7, 1.0), t),
+        let weight = power_heuristic(sample.pdf, bsdf_pdf);
+        Vec3::mul(
+            &Vec3::mul_vec(&f, &sample.emission),
+            transmittance * weight / sample.pdf,
         )
     }
 
-    pub fn get_ray(&self, i: usize, j: usize) -> Ray {
-        let offset = self.sample_square();
+    /// Where `ray` first runs into a particle of `medium` or of the fog
+    /// between `t_min` and `t_max`, and which of the two it was.
+    fn sample_collision(
+        &self,
+        ray: &Ray,
+        medium: Option<Medium>,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(f64, Medium)> {
+        let length = ray.direction.length();
+        let mut collision = None;
+        if let Some(medium) = medium {
+            let t = t_min + medium.sample_distance(sampler.next_1d()) / length;
+            if t < t_max {
+                collision = Some((t, medium));
+            }
+        }
+        if let Some(fog) = &self.fog {
+            if let Some((start, end)) = fog.segment(&self.center, ray, t_min, t_max) {
+                let t = start + fog.medium.sample_distance(sampler.next_1d()) / length;
+                if t < end && collision.is_none_or(|(first, _)| t < first) {
+                    collision = Some((t, fog.medium));
+                }
+            }
+        }
+        collision
+    }
+
+    /// The fraction of light that gets `distance` along the unit
+    /// `direction` from `origin` through volumes and fog, starting out in
+    /// `medium`. 0 if a surface is in the way.
+    fn transmittance(
+        &self,
+        world: &dyn Hittable,
+        origin: &Vec3,
+        direction: &Vec3,
+        distance: f64,
+        mut medium: Option<Medium>,
+    ) -> f64 {
+        let shadow_ray = Ray::new(origin, direction);
+        let t_max = distance - 0.001;
+        let mut t_min = 0.001;
+        let mut transmittance = 1.0;
+        loop {
+            let mut shadow_record = HitRecord::new();
+            let hit = world.hit(
+                &shadow_ray,
+                &Interval::new_with_values(t_min, t_max),
+                &mut shadow_record,
+            );
+            let end = if hit { shadow_record.t } else { t_max };
+            if let Some(medium) = medium {
+                transmittance *= medium.transmittance(end - t_min);
+            }
+            if !hit {
+                break;
+            }
+            match shadow_record.material {
+                Some(Material::Medium(volume)) => {
+                    medium = shadow_record.front_face.then_some(*volume);
+                    t_min = shadow_record.t + 0.001;
+                }
+                _ => return 0.0,
+            }
+        }
+        if let Some(fog) = &self.fog {
+            if let Some((start, end)) = fog.segment(&self.center, &shadow_ray, 0.0, distance) {
+                transmittance *= fog.medium.transmittance(end - start);
+            }
+        }
+        transmittance
+    }
+
+    /// A jittered ray through pixel `(i, j)`, starting on the lens when
+    /// depth of field is on. `None` if the lens barrel blocks it.
+    pub fn get_ray(&self, i: usize, j: usize, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let offset = self.sample_square(sampler);
 
         let pixel_center = Vec3::add(
             &self.pixel00_loc,
@@ -182,30 +651,61 @@ impl Camera {
         let ray_origin = if self.defocus_angle <= 0. {
             self.center
         } else {
-            self.defocus_disk_sample()
+            self.defocus_disk_sample(i, j, sampler)?
         };
-        let ray_direction = Vec3::sub(&pixel_center, &self.center);
-        Ray {
-            origin: ray_origin,
-            direction: ray_direction,
-        }
+        let ray_direction = Vec3::sub(&pixel_center, &ray_origin);
+        Some(Ray::new(&ray_origin, &ray_direction))
     }
-    pub fn defocus_disk_sample(&self) -> Vec3 {
-        let p = Vec3::random_in_unit_disk();
-        Vec3::add(
-            &self.center,
+    /// Ray from the camera center through the middle of pixel `(i, j)`,
+    /// without jitter or defocus.
+    pub fn pixel_ray(&self, i: usize, j: usize) -> Ray {
+        let pixel_center = Vec3::add(
+            &self.pixel00_loc,
             &Vec3::add(
-                &Vec3::mul(&self.defocus_disk_u, p.x),
-                &Vec3::mul(&self.defocus_disk_v, p.y),
+                &Vec3::mul(&self.pixel_delta_u, i as f64),
+                &Vec3::mul(&self.pixel_delta_v, j as f64),
             ),
-        )
+        );
+        Ray::new(&self.center, &Vec3::sub(&pixel_center, &self.center))
     }
-    pub fn sample_square(&self) -> Vec3 {
-        let mut rng = rand::thread_rng();
-        Vec3 {
-            x: rng.gen::<f64>(),
-            y: rng.gen::<f64>(),
-            z: 0.0,
+    /// A point on the lens for pixel `(i, j)`, or `None` if it falls outside
+    /// the clipping circle of the cat-eye effect.
+    pub fn defocus_disk_sample(
+        &self,
+        i: usize,
+        j: usize,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Vec3> {
+        let (x, y) = self.aperture.sample(sampler);
+        if self.cat_eye > 0.0 {
+            let half_width = 0.5 * self.image_width as f64;
+            let half_height = 0.5 * self.image_height as f64;
+            let scale = self.cat_eye / half_width.hypot(half_height);
+            let shift_x = (i as f64 + 0.5 - half_width) * scale;
+            let shift_y = (half_height - j as f64 - 0.5) * scale;
+            if (x - shift_x).powi(2) + (y - shift_y).powi(2) > 1.0 {
+                return None;
+            }
         }
+        Some(Vec3::add(
+            &self.center,
+            &Vec3::add(
+                &Vec3::mul(&self.defocus_disk_u, x),
+                &Vec3::mul(&self.defocus_disk_v, y),
+            ),
+        ))
+    }
+    pub fn sample_square(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let (x, y) = sampler.next_2d();
+        Vec3 { x, y, z: 0.0 }
+    }
+}
+
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b == 0.0 {
+        return 0.0;
     }
+    a / (a + b)
 }
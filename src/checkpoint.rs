@@ -0,0 +1,194 @@
+use crate::{
+    camera::Camera,
+    framebuffer::{Framebuffer, PixelStats},
+    render::RenderSettings,
+    vec3::Vec3,
+};
+use std::{
+    fmt, fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+const MAGIC: &[u8; 8] = b"RTCKPT01";
+
+// A checkpoint holds the samples of a render saved part way, to be
+// continued or given more samples later. Since the random numbers of every
+// sample only depend on the seed, the pixel and the sample index, the
+// per-pixel sample counts are all the sampler state there is to keep.
+//
+// Layout, all little-endian: the magic bytes, the scene hash, width and
+// height as u64, then for every pixel row by row from the top the sum of
+// its samples (3 f64), their count (u64), and the running luminance mean
+// and M2 used by adaptive sampling (2 f64).
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Invalid {
+        path: PathBuf,
+        message: String,
+    },
+    /// The checkpoint was made for another scene or with other settings.
+    Mismatch {
+        path: PathBuf,
+    },
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            CheckpointError::Invalid { path, message } => {
+                write!(f, "{}: not a valid checkpoint: {}", path.display(), message)
+            }
+            CheckpointError::Mismatch { path } => write!(
+                f,
+                "{}: the checkpoint was made from a different scene or with different settings",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CheckpointError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Hash of the scene file and of every camera setting a checkpoint can't be
+/// continued with a different value of: image size, bounces, seed, sampler
+/// and color mode. The sample count and adaptive sampling settings are left
+/// out so a render can be given more samples. Files the scene refers to, like
+/// meshes and textures, aren't included.
+pub fn scene_hash(scene_source: &[u8], camera: &Camera, settings: &RenderSettings) -> u64 {
+    let mut hash = Fnv1a::new();
+    hash.write(scene_source);
+    for value in [
+        camera.image_width as u64,
+        camera.image_height as u64,
+        settings.max_depth as u64,
+        settings.seed,
+        settings.sampler as u64,
+        settings.color_mode as u64,
+    ] {
+        hash.write(&value.to_le_bytes());
+    }
+    hash.0
+}
+
+/// Saves `framebuffer` as a checkpoint of the scene with hash `scene_hash`.
+/// Writes to a temporary file first and moves it into place, so a crash
+/// while saving leaves the previous checkpoint intact.
+pub fn save(
+    path: &Path,
+    scene_hash: u64,
+    framebuffer: &Framebuffer,
+) -> Result<(), CheckpointError> {
+    let io_error = |source| CheckpointError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    write(&temporary, scene_hash, framebuffer).map_err(io_error)?;
+    fs::rename(&temporary, path).map_err(io_error)
+}
+
+fn write(path: &Path, scene_hash: u64, framebuffer: &Framebuffer) -> io::Result<()> {
+    let mut file = BufWriter::new(fs::File::create(path)?);
+    file.write_all(MAGIC)?;
+    file.write_all(&scene_hash.to_le_bytes())?;
+    file.write_all(&(framebuffer.width as u64).to_le_bytes())?;
+    file.write_all(&(framebuffer.height as u64).to_le_bytes())?;
+    for pixel in &framebuffer.pixels {
+        for value in [pixel.sum.x, pixel.sum.y, pixel.sum.z] {
+            file.write_all(&value.to_le_bytes())?;
+        }
+        file.write_all(&(pixel.count as u64).to_le_bytes())?;
+        file.write_all(&pixel.mean.to_le_bytes())?;
+        file.write_all(&pixel.m2.to_le_bytes())?;
+    }
+    file.flush()
+}
+
+/// Loads a checkpoint, checking that it belongs to the scene with hash
+/// `scene_hash`.
+pub fn load(path: &Path, scene_hash: u64) -> Result<Framebuffer, CheckpointError> {
+    let invalid = |message: &str| CheckpointError::Invalid {
+        path: path.to_path_buf(),
+        message: message.to_string(),
+    };
+    let file = fs::File::open(path).map_err(|source| CheckpointError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let mut file = BufReader::new(file);
+    // a file that ends early is reported as invalid rather than as an I/O
+    // error
+    let mut read_u64 = || {
+        let mut bytes = [0; 8];
+        file.read_exact(&mut bytes)
+            .map(|_| u64::from_le_bytes(bytes))
+            .map_err(|_| invalid("the file is truncated"))
+    };
+
+    if read_u64()?.to_le_bytes() != *MAGIC {
+        return Err(invalid("unknown file format"));
+    }
+    if read_u64()? != scene_hash {
+        return Err(CheckpointError::Mismatch {
+            path: path.to_path_buf(),
+        });
+    }
+    let width = read_u64()? as usize;
+    let height = read_u64()? as usize;
+    let pixel_count = width
+        .checked_mul(height)
+        .filter(|&count| count <= 1 << 32)
+        .ok_or_else(|| invalid("the image size is too large"))?;
+    let mut pixels = Vec::with_capacity(pixel_count);
+    for _ in 0..pixel_count {
+        let sum = Vec3::new(
+            f64::from_bits(read_u64()?),
+            f64::from_bits(read_u64()?),
+            f64::from_bits(read_u64()?),
+        );
+        pixels.push(PixelStats {
+            sum,
+            count: read_u64()? as usize,
+            mean: f64::from_bits(read_u64()?),
+            m2: f64::from_bits(read_u64()?),
+        });
+    }
+    Ok(Framebuffer {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// 64-bit FNV-1a, which unlike the standard library's hasher is guaranteed
+/// to stay the same between builds.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use clap::{Args, Parser, Subcommand};
+use raytracer_rust::{
+    output::{ExrCompression, ExrPrecision, OutputOptions},
+    sampler::SamplerKind,
+    scene::LoadOptions,
+    tiles::{TileOrder, TileScheduler},
+    ColorMode, RenderSettings, Scene,
+};
+use std::path::PathBuf;
+
+const DEFAULT_SCENE: &str = "scenes/random_spheres.toml";
+
+#[derive(Parser)]
+#[command(version, about = "A CPU path tracer")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Render a scene to an image file (.png, .ppm, .pfm, .hdr or .exr)
+    Render {
+        scene: PathBuf,
+        output: PathBuf,
+        #[command(flatten)]
+        settings: RenderArgs,
+        #[command(flatten)]
+        output_args: OutputArgs,
+        /// Also save an image of how many samples each pixel took
+        #[arg(long)]
+        heatmap: Option<PathBuf>,
+        /// Save the render's progress to this file every so often and when
+        /// it ends, so it can be resumed
+        #[arg(long)]
+        checkpoint: Option<PathBuf>,
+        /// Seconds between checkpoints
+        #[arg(long, default_value_t = 60.0, value_parser = positive_f64)]
+        checkpoint_interval: f64,
+        /// Continue the render saved in --checkpoint, e.g. after a crash or
+        /// with a higher --spp to add samples to a finished render
+        #[arg(long, requires = "checkpoint")]
+        resume: bool,
+    },
+    /// Render a scene on worker processes that connect over the network
+    Coordinate {
+        scene: PathBuf,
+        output: PathBuf,
+        /// Address to wait for workers on
+        #[arg(long, default_value = "127.0.0.1:7878")]
+        listen: String,
+        /// Seconds to wait on a worker before giving its tile to another
+        #[arg(long, default_value_t = 600.0, value_parser = positive_f64)]
+        worker_timeout: f64,
+        #[command(flatten)]
+        settings: RenderArgs,
+        #[command(flatten)]
+        output_args: OutputArgs,
+    },
+    /// Render tiles for a coordinator until its render is done
+    Worker {
+        /// Address of the coordinator
+        #[arg(default_value = "127.0.0.1:7878")]
+        coordinator: String,
+        /// Number of worker threads (default: one per core)
+        #[arg(long, value_parser = positive)]
+        threads: Option<usize>,
+    },
+    /// Render a scene and show it in a window
+    Preview {
+        #[arg(default_value = DEFAULT_SCENE)]
+        scene: PathBuf,
+        #[command(flatten)]
+        settings: RenderArgs,
+    },
+    /// Time repeated renders of a scene
+    Bench {
+        #[arg(default_value = DEFAULT_SCENE)]
+        scene: PathBuf,
+        /// Number of timed renders
+        #[arg(long, default_value_t = 3, value_parser = positive)]
+        runs: usize,
+        #[command(flatten)]
+        settings: RenderArgs,
+    },
+    /// Check scene files for errors without rendering them
+    Validate {
+        #[arg(required = true)]
+        scenes: Vec<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+pub struct OutputArgs {
+    /// Sample type of EXR output
+    #[arg(long, value_enum, default_value_t)]
+    pub exr_precision: ExrPrecision,
+    /// Compression of EXR output
+    #[arg(long, value_enum, default_value_t)]
+    pub exr_compression: ExrCompression,
+}
+
+impl OutputArgs {
+    pub fn options(&self) -> OutputOptions {
+        OutputOptions {
+            exr_precision: self.exr_precision,
+            exr_compression: self.exr_compression,
+        }
+    }
+}
+
+/// Overrides for the `[render]` table of the scene file.
+#[derive(Args)]
+pub struct RenderArgs {
+    /// Image width in pixels
+    #[arg(long, value_parser = positive)]
+    pub width: Option<usize>,
+    /// Image height in pixels (default: from the scene's aspect ratio)
+    #[arg(long, value_parser = positive)]
+    pub height: Option<usize>,
+    /// Samples per pixel
+    #[arg(long, value_parser = positive)]
+    pub spp: Option<usize>,
+    /// Maximum number of bounces per path
+    #[arg(long, value_parser = positive)]
+    pub max_depth: Option<usize>,
+    /// Seed for all random choices; the same seed gives the same image
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// How sample values are generated; low-discrepancy samplers give less
+    /// noise at the same sample count
+    #[arg(long, value_enum)]
+    pub sampler: Option<SamplerKind>,
+    /// Trace RGB colors, or a few wavelengths per path for dispersion and
+    /// other effects that depend on the spectrum
+    #[arg(long, value_enum)]
+    pub color_mode: Option<ColorMode>,
+    /// Turn on adaptive sampling: pixels stop taking samples once their
+    /// noise drops below this (e.g. 0.01), with --spp as the upper bound
+    #[arg(long, value_parser = positive_f64)]
+    pub noise_threshold: Option<f64>,
+    /// Samples every pixel takes before adaptive sampling may stop it
+    #[arg(long, value_parser = positive)]
+    pub min_spp: Option<usize>,
+    /// Number of worker threads (default: one per core)
+    #[arg(long, value_parser = positive)]
+    pub threads: Option<usize>,
+    /// Width and height of the tiles the image is rendered in
+    #[arg(long, default_value_t = 32, value_parser = positive)]
+    pub tile_size: usize,
+    /// Order the tiles are rendered in
+    #[arg(long, value_enum, default_value_t)]
+    pub tile_order: TileOrder,
+}
+
+impl RenderArgs {
+    pub fn load_options(&self) -> LoadOptions {
+        LoadOptions {
+            width: self.width,
+            height: self.height,
+            samples_per_pixel: self.spp,
+            max_depth: self.max_depth,
+            seed: self.seed,
+            sampler: self.sampler,
+            color_mode: self.color_mode,
+            noise_threshold: self.noise_threshold,
+            min_samples_per_pixel: self.min_spp,
+        }
+    }
+
+    pub fn scheduler(&self) -> TileScheduler {
+        TileScheduler::new(self.tile_size, self.tile_order)
+    }
+
+    /// The scene's render settings with the tile options applied; the rest
+    /// are applied when loading the scene.
+    pub fn render_settings(&self, scene: &Scene) -> RenderSettings {
+        RenderSettings {
+            tiles: self.scheduler(),
+            ..scene.settings
+        }
+    }
+}
+
+fn positive(arg: &str) -> Result<usize, String> {
+    match arg.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(value) => Ok(value),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn positive_f64(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(value) if value > 0.0 => Ok(value),
+        Ok(_) => Err("must be positive".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
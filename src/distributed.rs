@@ -0,0 +1,339 @@
+use crate::{
+    framebuffer::{Framebuffer, PixelStats},
+    scene::{LoadOptions, Scene},
+    tiles::{Progress, Tile},
+    vec3::Vec3,
+};
+use rayon::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+// Coordinator and workers talk over TCP in JSON, one message per line. A
+// worker connects, gets the scene and answers with `Ready`, then is sent one
+// tile at a time until there are none left. Every random number depends
+// only on the seed, the pixel and the sample index, so it doesn't matter
+// which worker renders a tile: the image comes out the same as a local
+// render.
+
+/// Messages from the coordinator to a worker.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Scene {
+        /// Where the scene file is on the coordinator. Meshes and images it
+        /// refers to are loaded relative to it, so workers on other machines
+        /// need them at the same place.
+        path: PathBuf,
+        source: String,
+        options: LoadOptions,
+    },
+    Tile {
+        tile: Tile,
+    },
+    Done,
+}
+
+/// Messages from a worker to the coordinator.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Response {
+    Ready,
+    /// The samples of the tile's pixels, row by row, see `encode_pixel`.
+    Tile {
+        tile: Tile,
+        pixels: Vec<[u64; 6]>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// What the coordinator sends every worker.
+pub struct Job {
+    pub scene_path: PathBuf,
+    pub source: String,
+    pub options: LoadOptions,
+    /// How long a worker may go quiet before it is dropped and its tile
+    /// given to another, so it has to be longer than a tile takes.
+    pub timeout: Duration,
+}
+
+/// Tiles waiting to be handed out, and how many aren't back yet.
+struct WorkQueue {
+    pending: VecDeque<Tile>,
+    remaining: usize,
+}
+
+/// Hands `tiles` out to the workers that connect to `listener` and adds
+/// the samples they send back to `framebuffer`, returning it once every
+/// tile is in. Workers can come and go at any time; the tile of a worker
+/// that disconnects, fails or times out goes back to the queue for the next
+/// one.
+pub fn coordinate(
+    listener: TcpListener,
+    job: &Job,
+    tiles: Vec<Tile>,
+    framebuffer: Framebuffer,
+    progress: &(dyn Fn(&Progress) + Sync),
+) -> io::Result<Framebuffer> {
+    let tiles_total = tiles.len();
+    let queue = Mutex::new(WorkQueue {
+        remaining: tiles.len(),
+        pending: tiles.into(),
+    });
+    let changed = Condvar::new();
+    let framebuffer = Mutex::new(framebuffer);
+    let start = Instant::now();
+
+    // called with each finished tile
+    let finish = |tile: Tile, pixels: Vec<PixelStats>| {
+        {
+            let mut framebuffer = framebuffer.lock().unwrap();
+            for (index, stats) in pixels.into_iter().enumerate() {
+                let (i, j) = (tile.x + index % tile.width, tile.y + index / tile.width);
+                let index = framebuffer.index(i, j);
+                framebuffer.pixels[index] = stats;
+            }
+        }
+        let remaining = {
+            let mut queue = queue.lock().unwrap();
+            queue.remaining -= 1;
+            queue.remaining
+        };
+        changed.notify_all();
+        progress(&Progress {
+            tile,
+            tiles_done: tiles_total - remaining,
+            tiles_total,
+            elapsed: start.elapsed(),
+        });
+    };
+    // the next tile for a worker, or `None` once all tiles are back
+    let next_tile = || {
+        let mut queue = queue.lock().unwrap();
+        loop {
+            if queue.remaining == 0 {
+                return None;
+            }
+            if let Some(tile) = queue.pending.pop_front() {
+                return Some(tile);
+            }
+            queue = changed.wait(queue).unwrap();
+        }
+    };
+    let give_back = |tile: Tile| {
+        queue.lock().unwrap().pending.push_front(tile);
+        changed.notify_all();
+    };
+
+    // polled so the loop can stop accepting once the render is done
+    listener.set_nonblocking(true)?;
+    thread::scope(|scope| {
+        while queue.lock().unwrap().remaining > 0 {
+            match listener.accept() {
+                Ok((stream, address)) => {
+                    eprintln!("worker {} connected", address);
+                    let (finish, next_tile, give_back) = (&finish, &next_tile, &give_back);
+                    scope.spawn(move || {
+                        match serve_worker(stream, job, next_tile, give_back, finish) {
+                            Ok(()) => eprintln!("worker {} finished", address),
+                            Err(err) => eprintln!("worker {} dropped: {}", address, err),
+                        }
+                    });
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(50));
+                }
+                // e.g. a connection reset before it was accepted
+                Err(err) => eprintln!("accepting a worker failed: {}", err),
+            }
+        }
+    });
+    Ok(framebuffer.into_inner().unwrap())
+}
+
+/// Talks to one worker until the render is done or the connection fails.
+fn serve_worker(
+    stream: TcpStream,
+    job: &Job,
+    next_tile: &dyn Fn() -> Option<Tile>,
+    give_back: &dyn Fn(Tile),
+    finish: &dyn Fn(Tile, Vec<PixelStats>),
+) -> Result<(), Box<dyn Error>> {
+    stream.set_nonblocking(false)?;
+    // a worker that hangs without closing the connection would otherwise
+    // keep its tile, and the render, waiting forever
+    stream.set_read_timeout(Some(job.timeout))?;
+    stream.set_write_timeout(Some(job.timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    send(
+        &mut writer,
+        &Request::Scene {
+            path: job.scene_path.clone(),
+            source: job.source.clone(),
+            options: job.options.clone(),
+        },
+    )?;
+    match receive(&mut reader)? {
+        Response::Ready => {}
+        Response::Error { message } => return Err(message.into()),
+        Response::Tile { .. } => return Err("unexpected tile before the scene was loaded".into()),
+    }
+
+    while let Some(tile) = next_tile() {
+        match render_remotely(&mut reader, &mut writer, tile) {
+            Ok(pixels) => finish(tile, pixels),
+            Err(err) => {
+                give_back(tile);
+                return Err(err);
+            }
+        }
+    }
+    send(&mut writer, &Request::Done)?;
+    Ok(())
+}
+
+fn render_remotely(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    tile: Tile,
+) -> Result<Vec<PixelStats>, Box<dyn Error>> {
+    send(writer, &Request::Tile { tile })?;
+    match receive(reader)? {
+        Response::Tile {
+            tile: returned,
+            pixels,
+        } if returned == tile && pixels.len() == tile.width * tile.height => {
+            Ok(pixels.iter().map(decode_pixel).collect())
+        }
+        Response::Tile { .. } => Err("sent back the wrong tile".into()),
+        Response::Error { message } => Err(message.into()),
+        Response::Ready => Err("unexpected message".into()),
+    }
+}
+
+/// Connects to the coordinator at `address` and renders the tiles it hands
+/// out until it says the render is done.
+pub fn work(address: &str) -> Result<(), Box<dyn Error>> {
+    let stream = TcpStream::connect(address)?;
+    let coordinator: SocketAddr = stream.peer_addr()?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let Request::Scene {
+        path,
+        source,
+        options,
+    } = receive(&mut reader)?
+    else {
+        return Err("expected a scene from the coordinator".into());
+    };
+    let scene = match Scene::parse(&source, &path, &options) {
+        Ok(scene) => scene,
+        Err(err) => {
+            send(
+                &mut writer,
+                &Response::Error {
+                    message: err.to_string(),
+                },
+            )?;
+            return Err(err.into());
+        }
+    };
+    send(&mut writer, &Response::Ready)?;
+    eprintln!("rendering {} for {}", path.display(), coordinator);
+
+    let mut tiles_done = 0;
+    loop {
+        match receive(&mut reader)? {
+            Request::Tile { tile } => {
+                let pixels = (0..tile.width * tile.height)
+                    .into_par_iter()
+                    .map(|index| {
+                        let (i, j) = (tile.x + index % tile.width, tile.y + index / tile.width);
+                        let mut stats = PixelStats::default();
+                        scene.camera.process_ray(
+                            i,
+                            j,
+                            scene.world.as_ref(),
+                            &scene.lights,
+                            &scene.settings,
+                            &mut stats,
+                        );
+                        encode_pixel(&stats)
+                    })
+                    .collect();
+                send(&mut writer, &Response::Tile { tile, pixels })?;
+                tiles_done += 1;
+            }
+            Request::Done => break,
+            Request::Scene { .. } => return Err("unexpected second scene".into()),
+        }
+    }
+    eprintln!("done, rendered {} tiles", tiles_done);
+    Ok(())
+}
+
+fn send(writer: &mut impl Write, message: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    writer.flush()
+}
+
+fn receive<T: DeserializeOwned>(reader: &mut impl BufRead) -> io::Result<T> {
+    let mut line = String::new();
+    let read = reader
+        .read_line(&mut line)
+        .map_err(|err| match err.kind() {
+            // what a timeout looks like depends on the platform
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                io::Error::new(io::ErrorKind::TimedOut, "timed out")
+            }
+            _ => err,
+        })?;
+    if read == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed",
+        ));
+    }
+    serde_json::from_str(&line).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Pixel statistics as the bits of their floats, since JSON can't hold NaN
+/// or infinity and needn't parse floats back exactly.
+fn encode_pixel(stats: &PixelStats) -> [u64; 6] {
+    [
+        stats.sum.x.to_bits(),
+        stats.sum.y.to_bits(),
+        stats.sum.z.to_bits(),
+        stats.count as u64,
+        stats.mean.to_bits(),
+        stats.m2.to_bits(),
+    ]
+}
+
+fn decode_pixel(bits: &[u64; 6]) -> PixelStats {
+    PixelStats {
+        sum: Vec3::new(
+            f64::from_bits(bits[0]),
+            f64::from_bits(bits[1]),
+            f64::from_bits(bits[2]),
+        ),
+        count: bits[3] as usize,
+        mean: f64::from_bits(bits[4]),
+        m2: f64::from_bits(bits[5]),
+    }
+}
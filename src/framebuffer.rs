@@ -0,0 +1,99 @@
+use crate::vec3::Vec3;
+
+/// Linear radiance, stored row by row from the top.
+#[derive(Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Image {
+    pub fn get(&self, i: usize, j: usize) -> Vec3 {
+        self.pixels[j * self.width + i]
+    }
+
+    pub fn rows(&self) -> std::slice::ChunksExact<'_, Vec3> {
+        self.pixels.chunks_exact(self.width.max(1))
+    }
+}
+
+/// The samples taken for one pixel so far: their sum, and the running mean
+/// and variance of their luminance (Welford's method) for adaptive sampling.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PixelStats {
+    pub sum: Vec3,
+    pub count: usize,
+    pub mean: f64,
+    pub m2: f64,
+}
+
+impl PixelStats {
+    pub fn add(&mut self, color: &Vec3) {
+        let luminance = 0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z;
+        self.sum = Vec3::add(&self.sum, color);
+        self.count += 1;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (luminance - self.mean);
+    }
+
+    /// The average of the samples, black if there are none.
+    pub fn color(&self) -> Vec3 {
+        if self.count == 0 {
+            Vec3::default()
+        } else {
+            Vec3::div(&self.sum, self.count as f64)
+        }
+    }
+
+    /// Standard error of the mean after gamma 2 encoding: the error of
+    /// `sqrt(mean)` is about `error(mean) / (2 sqrt(mean))`.
+    pub fn error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        let variance = self.m2 / (self.count - 1) as f64;
+        let standard_error = (variance / self.count as f64).sqrt();
+        standard_error / (2.0 * self.mean.max(1e-4).sqrt())
+    }
+}
+
+/// A render in progress, stored row by row from the top. More samples can
+/// be added to it at any time, which is how renders are resumed.
+#[derive(Clone)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<PixelStats>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![PixelStats::default(); width * height],
+        }
+    }
+
+    pub fn index(&self, i: usize, j: usize) -> usize {
+        j * self.width + i
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> &PixelStats {
+        &self.pixels[self.index(i, j)]
+    }
+
+    pub fn to_image(&self) -> Image {
+        Image {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().map(PixelStats::color).collect(),
+        }
+    }
+
+    pub fn total_samples(&self) -> usize {
+        self.pixels.iter().map(|pixel| pixel.count).sum()
+    }
+}
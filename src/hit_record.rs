@@ -1,14 +1,24 @@
-use crate::{interval::Interval, material::Material, ray::Ray, Vec3};
+use crate::{aabb::Aabb, interval::Interval, material::Material, ray::Ray, vec3::Vec3};
 
 #[derive(Clone)]
-pub struct HitRecord {
+pub struct HitRecord<'a> {
     pub point: Vec3,
     pub normal: Vec3,
     pub t: f64,
     pub front_face: bool,
-    pub material: Option<Material>,
+    pub material: Option<&'a Material>,
+    /// Surface texture coordinates.
+    pub u: f64,
+    pub v: f64,
+    /// Barycentric weights of the hit point for triangle primitives.
+    pub barycentric: Vec3,
 }
-impl HitRecord {
+impl Default for HitRecord<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl HitRecord<'_> {
     pub fn new() -> Self {
         Self {
             point: Vec3::default(),
@@ -16,6 +26,9 @@ impl HitRecord {
             t: 0.0,
             front_face: false,
             material: None,
+            u: 0.0,
+            v: 0.0,
+            barycentric: Vec3::default(),
         }
     }
     pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: &Vec3) {
@@ -29,5 +42,6 @@ impl HitRecord {
 }
 
 pub trait Hittable: Send + Sync {
-    fn hit(&self, ray: &Ray, interval: &Interval, hit_record: &mut HitRecord) -> bool;
+    fn hit<'a>(&'a self, ray: &Ray, interval: &Interval, hit_record: &mut HitRecord<'a>) -> bool;
+    fn bounding_box(&self) -> Aabb;
 }
@@ -1,25 +1,40 @@
 use crate::{
+    aabb::Aabb,
     hit_record::{HitRecord, Hittable},
     interval::Interval,
 };
 
 pub struct HittableList {
     pub objects: Vec<Box<dyn Hittable>>,
+    bbox: Aabb,
 }
 
 impl HittableList {
     pub fn new() -> Self {
         Self {
             objects: Vec::new(),
+            bbox: Aabb::new(),
         }
     }
     pub fn push(&mut self, obj: Box<dyn Hittable>) {
+        self.bbox = Aabb::union(&self.bbox, &obj.bounding_box());
         self.objects.push(obj);
     }
 }
 
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Hittable for HittableList {
-    fn hit(&self, ray: &crate::ray::Ray, interval: &Interval, hit_record: &mut HitRecord) -> bool {
+    fn hit<'a>(
+        &'a self,
+        ray: &crate::ray::Ray,
+        interval: &Interval,
+        hit_record: &mut HitRecord<'a>,
+    ) -> bool {
         let mut tmp_record = HitRecord::new();
         let mut hit_anything = false;
         let mut closest = interval.max;
@@ -36,4 +51,7 @@ impl Hittable for HittableList {
         }
         hit_anything
     }
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
 }
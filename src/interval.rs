@@ -1,9 +1,15 @@
 use core::f64;
 
+#[derive(Clone, Copy, Debug)]
 pub struct Interval {
     pub min: f64,
     pub max: f64,
 }
+impl Default for Interval {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl Interval {
     pub fn new() -> Self {
         Self {
@@ -14,6 +20,12 @@ impl Interval {
     pub fn new_with_values(min: f64, max: f64) -> Self {
         Self { min, max }
     }
+    pub fn new_enclosing(a: &Interval, b: &Interval) -> Self {
+        Self {
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+        }
+    }
     pub fn contains(&self, x: f64) -> bool {
         self.min <= x && x <= self.max
     }
@@ -23,6 +35,13 @@ impl Interval {
     pub fn size(&self) -> f64 {
         self.max - self.min
     }
+    pub fn expand(&self, delta: f64) -> Self {
+        let padding = delta / 2.0;
+        Self {
+            min: self.min - padding,
+            max: self.max + padding,
+        }
+    }
     pub fn clamp(&self, x: f64) -> f64 {
         if x < self.min {
             self.min
@@ -0,0 +1,42 @@
+//! A CPU path tracer, following Ray Tracing in One Weekend.
+//!
+//! Load a scene file with [`Scene::load`] or put one together with
+//! [`Camera::builder`] and [`Scene::new`], then [`render`] it with some
+//! [`RenderSettings`].
+
+pub mod aabb;
+pub mod aperture;
+pub mod bvh;
+pub mod camera;
+pub mod checkpoint;
+pub mod distributed;
+pub mod framebuffer;
+pub mod hit_record;
+pub mod hittable_list;
+pub mod interval;
+pub mod light;
+pub mod material;
+pub mod medium;
+pub mod microfacet;
+pub mod obj;
+pub mod output;
+pub mod principled;
+pub mod quad;
+pub mod ray;
+pub mod render;
+mod rgb_to_spectrum;
+pub mod sampler;
+pub mod scene;
+pub mod spectrum;
+pub mod sphere;
+pub mod texture;
+pub mod tiles;
+pub mod triangle;
+pub mod triangle_mesh;
+pub mod vec3;
+
+pub use camera::{Background, Camera, CameraBuilder};
+pub use framebuffer::{Framebuffer, Image};
+pub use render::{render, render_with, AdaptiveSampling, ColorMode, RenderSettings};
+pub use scene::Scene;
+pub use vec3::Vec3;
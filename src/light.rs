@@ -0,0 +1,195 @@
+use crate::{
+    hit_record::{HitRecord, Hittable},
+    interval::Interval,
+    quad::Quad,
+    ray::Ray,
+    sampler::Sampler,
+    spectrum::Wavelengths,
+    sphere::Sphere,
+    vec3::Vec3,
+};
+use std::f64::consts::PI;
+
+/// Emitter geometry that can be sampled directly for next-event estimation.
+#[derive(Clone)]
+pub enum Light {
+    Sphere(Sphere),
+    Quad(Quad),
+}
+
+pub struct LightSample {
+    /// Unit direction from the shading point towards the sampled point.
+    pub direction: Vec3,
+    pub distance: f64,
+    /// Radiance leaving the light towards the shading point, in the
+    /// wavelengths it was sampled for or else RGB.
+    pub emission: Vec3,
+    /// Solid-angle density of `direction` for this light alone.
+    pub pdf: f64,
+}
+
+impl Light {
+    fn shape(&self) -> &dyn Hittable {
+        match self {
+            Light::Sphere(sphere) => sphere,
+            Light::Quad(quad) => quad,
+        }
+    }
+
+    pub fn sample(
+        &self,
+        origin: &Vec3,
+        wavelengths: Option<Wavelengths>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<LightSample> {
+        let direction = match self {
+            Light::Sphere(sphere) => {
+                // uniform over the cone of directions the sphere subtends
+                let to_center = Vec3::sub(&sphere.center, origin);
+                let distance_squared = to_center.length_squared();
+                let radius_squared = sphere.radius * sphere.radius;
+                if distance_squared <= radius_squared {
+                    return None;
+                }
+                let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+                let (r1, r2) = sampler.next_2d();
+                let cos_theta = 1.0 + r1 * (cos_theta_max - 1.0);
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * r2;
+                let (u, v, w) = Vec3::orthonormal_basis(&Vec3::unit(&to_center));
+                Vec3::add(
+                    &Vec3::add(
+                        &Vec3::mul(&u, phi.cos() * sin_theta),
+                        &Vec3::mul(&v, phi.sin() * sin_theta),
+                    ),
+                    &Vec3::mul(&w, cos_theta),
+                )
+            }
+            Light::Quad(quad) => {
+                let (r1, r2) = sampler.next_2d();
+                let point = Vec3::add(
+                    &quad.q,
+                    &Vec3::add(&Vec3::mul(&quad.u, r1), &Vec3::mul(&quad.v, r2)),
+                );
+                Vec3::unit(&Vec3::sub(&point, origin))
+            }
+        };
+
+        // find the sampled point again so distance, emission and pdf all
+        // come from the same intersection
+        let hit = self.intersect(origin, &direction)?;
+        let pdf = self.pdf_at(origin, &direction, &hit);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let mut ray = Ray::new(origin, &direction);
+        ray.wavelengths = wavelengths;
+        let emission = hit
+            .material
+            .map_or(Vec3::default(), |material| material.emitted(&ray, &hit));
+        Some(LightSample {
+            direction,
+            distance: hit.t,
+            emission,
+            pdf,
+        })
+    }
+
+    /// Solid-angle density with which `sample` would pick `direction`, if the
+    /// first intersection with this light is at distance `t`.
+    fn pdf_for_hit(&self, origin: &Vec3, direction: &Vec3, t: f64) -> f64 {
+        match self.intersect(origin, direction) {
+            Some(hit) if (hit.t - t).abs() <= 1e-6 * t.max(1.0) => {
+                self.pdf_at(origin, direction, &hit)
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn intersect(&self, origin: &Vec3, direction: &Vec3) -> Option<HitRecord<'_>> {
+        let mut hit = HitRecord::new();
+        let ray = Ray::new(origin, direction);
+        if self.shape().hit(
+            &ray,
+            &Interval::new_with_values(0.001, f64::INFINITY),
+            &mut hit,
+        ) {
+            Some(hit)
+        } else {
+            None
+        }
+    }
+
+    fn pdf_at(&self, origin: &Vec3, direction: &Vec3, hit: &HitRecord) -> f64 {
+        match self {
+            Light::Sphere(sphere) => {
+                let distance_squared = Vec3::sub(&sphere.center, origin).length_squared();
+                let radius_squared = sphere.radius * sphere.radius;
+                if distance_squared <= radius_squared {
+                    return 0.0;
+                }
+                let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+                1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+            }
+            Light::Quad(quad) => {
+                let distance = hit.t * direction.length();
+                let cosine = (Vec3::dot(direction, &quad.normal()) / direction.length()).abs();
+                if cosine < 1e-8 {
+                    return 0.0;
+                }
+                distance * distance / (cosine * quad.area())
+            }
+        }
+    }
+}
+
+/// The lights of a scene. Sampling picks one light uniformly.
+#[derive(Clone, Default)]
+pub struct LightList {
+    pub lights: Vec<Light>,
+}
+impl LightList {
+    pub fn new() -> Self {
+        Self { lights: Vec::new() }
+    }
+    pub fn push(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    /// Samples a direction towards one of the lights. The returned pdf
+    /// includes the probability of choosing that light.
+    pub fn sample(
+        &self,
+        origin: &Vec3,
+        wavelengths: Option<Wavelengths>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<LightSample> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let index =
+            ((sampler.next_1d() * self.lights.len() as f64) as usize).min(self.lights.len() - 1);
+        let mut sample = self.lights[index].sample(origin, wavelengths, sampler)?;
+        sample.pdf /= self.lights.len() as f64;
+        Some(sample)
+    }
+
+    /// Density with which `sample` would have produced a ray from `origin`
+    /// along `direction` that first hits a light at distance `t`. Only lights
+    /// whose surface is at that distance count, so an emitter that is not in
+    /// this list gets zero.
+    pub fn pdf(&self, origin: &Vec3, direction: &Vec3, t: f64) -> f64 {
+        if self.lights.is_empty() {
+            return 0.0;
+        }
+        let total: f64 = self
+            .lights
+            .iter()
+            .map(|light| light.pdf_for_hit(origin, direction, t))
+            .sum();
+        total / self.lights.len() as f64
+    }
+}
@@ -1,187 +1,283 @@
-#![allow(dead_code)]
 extern crate sdl2;
 
-mod camera;
-mod hit_record;
-mod hittable_list;
-mod interval;
-mod material;
-mod ray;
-mod sphere;
-mod vec3;
+mod cli;
+mod preview;
 
-use camera::Camera;
-use core::f64;
-use hittable_list::HittableList;
-use interval::Interval;
-use material::Material;
-use rand::Rng;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::rect::Point;
-use sphere::Sphere;
-use std::fs::OpenOptions;
-use std::io::{self, Write};
+use clap::Parser;
+use cli::{Cli, Command, RenderArgs};
+use raytracer_rust::{
+    checkpoint, distributed,
+    output::{self, ImageFormat, OutputError},
+    render_with,
+    scene::SceneError,
+    tiles::{Progress, RenderHooks},
+    Framebuffer, Scene,
+};
+use std::error::Error;
+use std::fs;
+use std::io::IsTerminal;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use vec3::Vec3;
+use std::time::{Duration, Instant};
 
-const ASPECT_RATIO: f64 = 16. / 9.;
-const IMAGE_WIDTH: usize = 900;
-const IMAGE_HEIGHT: usize = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as usize;
-
-fn print_image(buff_data: &[Vec<Vec3>]) -> io::Result<()> {
-    let mut file = OpenOptions::new().write(true).open("out.ppm")?;
-    file.write_all(b"P3\n")?;
-    file.write_all(
-        format!("{} {}\n", IMAGE_WIDTH, IMAGE_HEIGHT)
-            .to_string()
-            .as_bytes(),
-    )?;
-    file.write_all(b"255\n")?;
-
-    for (j, row) in buff_data.iter().enumerate() {
-        eprintln!("Lines remaining {}", IMAGE_HEIGHT - j);
-        for data in row.iter() {
-            let ir = (255.999 * data.x) as isize;
-            let ig = (255.999 * data.y) as isize;
-            let ib = (255.999 * data.z) as isize;
-            file.write_all(format!("{} {} {}\n", ir, ig, ib).as_bytes())?;
-        }
+fn main() {
+    let cli = Cli::parse();
+    if let Err(err) = run(cli.command) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
     }
-    Ok(())
 }
 
-fn main() {
-    let output = std::env::args().nth(1).unwrap_or_else(|| "sdl".to_string());
-    let range: i32 = std::env::args()
-        .nth(2)
-        .unwrap_or_else(|| "2".to_string())
-        .parse()
-        .unwrap();
-
-    let mut world = HittableList::new();
-
-    let ground_material = Material::Lambertian {
-        albedo: Vec3::new(0.5, 0.5, 0.5),
-    };
-    world.push(Box::new(Sphere::new(
-        &Vec3::new(0.0, -1000., -1.0),
-        1000.0,
-        ground_material,
-    )));
+fn run(command: Command) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Render {
+            scene,
+            output,
+            settings,
+            output_args,
+            heatmap,
+            checkpoint,
+            checkpoint_interval,
+            resume,
+        } => {
+            check_output_format(std::iter::once(&output).chain(&heatmap))?;
+            configure_threads(settings.threads)?;
+            let scene_path = scene;
+            let scene = Scene::load_with(&scene_path, &settings.load_options())?;
+            let render_settings = settings.render_settings(&scene);
+            let scene_hash =
+                checkpoint::scene_hash(&fs::read(&scene_path)?, &scene.camera, &render_settings);
+            let framebuffer = match &checkpoint {
+                Some(path) if resume => {
+                    let framebuffer = checkpoint::load(path, scene_hash)?;
+                    eprintln!(
+                        "resuming from {} with {} samples",
+                        path.display(),
+                        framebuffer.total_samples()
+                    );
+                    framebuffer
+                }
+                _ => Framebuffer::new(scene.camera.image_width, scene.camera.image_height),
+            };
+            let save_checkpoint = |framebuffer: &Framebuffer| {
+                if let Some(path) = &checkpoint {
+                    if let Err(err) = checkpoint::save(path, scene_hash, framebuffer) {
+                        eprintln!("warning: {}", err);
+                    }
+                }
+            };
 
-    let mut rng = rand::thread_rng();
-    for a in -range..range {
-        for b in -range..range {
-            let mat = rng.gen::<f64>();
-            let center = Vec3::new(
-                a as f64 + 0.9 * rng.gen::<f64>(),
-                0.2,
-                b as f64 + 0.9 * rng.gen::<f64>(),
+            let cancel = Arc::new(AtomicBool::new(false));
+            {
+                let cancel = cancel.clone();
+                ctrlc::set_handler(move || {
+                    if cancel.swap(true, Ordering::Relaxed) {
+                        std::process::exit(130);
+                    }
+                    eprintln!(
+                        "\nstopping after the pixels in progress, press Ctrl-C again to quit"
+                    );
+                })?;
+            }
+            let show_progress = std::io::stderr().is_terminal();
+            let start = Instant::now();
+            let progress = |progress: &Progress| {
+                if show_progress {
+                    report_progress(progress)
+                }
+            };
+            let hooks = RenderHooks {
+                progress: &progress,
+                cancel: &cancel,
+                checkpoint: checkpoint
+                    .is_some()
+                    .then_some(&save_checkpoint as &(dyn Fn(&Framebuffer) + Sync)),
+                checkpoint_interval: Duration::from_secs_f64(checkpoint_interval),
+            };
+            let framebuffer = render_with(&scene, &render_settings, framebuffer, &hooks);
+            if show_progress {
+                eprintln!();
+            }
+            save_checkpoint(&framebuffer);
+            let pixels = framebuffer.width * framebuffer.height;
+            eprintln!(
+                "rendered in {:.2?} ({:.1} spp on average)",
+                start.elapsed(),
+                framebuffer.total_samples() as f64 / pixels.max(1) as f64
             );
-            if Vec3::sub(&center, &Vec3::new(4., 0.2, 0.)).length() > 0.9 {
-                let material: Material = if mat < 0.8 {
-                    let albedo = Vec3::mul_vec(&Vec3::new_rand(), &Vec3::new_rand());
-                    Material::Lambertian { albedo }
-                } else if mat < 0.95 {
-                    let albedo = Vec3::new_rand_ranged(0.5, 1.);
-                    let fuzz = 0. + (0.5 * rng.gen::<f64>());
-                    Material::Metal { albedo, fuzz }
-                } else {
-                    Material::Dielectric {
-                        refraction_index: 1.5,
+            let options = output_args.options();
+            output::write_image_with(&output, &framebuffer.to_image(), &options)?;
+            if let Some(heatmap) = heatmap {
+                let counts =
+                    output::sample_heatmap(&framebuffer, render_settings.samples_per_pixel);
+                output::write_image_with(&heatmap, &counts, &options)?;
+            }
+            if cancel.load(Ordering::Relaxed) {
+                return Err("render cancelled, only the finished pixels were saved".into());
+            }
+        }
+        Command::Coordinate {
+            scene,
+            output,
+            listen,
+            worker_timeout,
+            settings,
+            output_args,
+        } => {
+            check_output_format([&output])?;
+            let options = settings.load_options();
+            // workers load the scene's meshes and images from the same place
+            let io_error = |source| SceneError::Io {
+                path: scene.clone(),
+                source,
+            };
+            let scene_path = fs::canonicalize(&scene).map_err(io_error)?;
+            let source = fs::read_to_string(&scene_path).map_err(io_error)?;
+            let camera = Scene::parse(&source, &scene_path, &options)?.camera;
+            let (width, height) = (camera.image_width, camera.image_height);
+
+            let listener = TcpListener::bind(&listen)?;
+            eprintln!("waiting for workers on {}", listener.local_addr()?);
+            let job = distributed::Job {
+                scene_path,
+                source,
+                options,
+                timeout: Duration::from_secs_f64(worker_timeout),
+            };
+            let show_progress = std::io::stderr().is_terminal();
+            let start = Instant::now();
+            let framebuffer = distributed::coordinate(
+                listener,
+                &job,
+                settings.scheduler().tiles(width, height),
+                Framebuffer::new(width, height),
+                &|progress| {
+                    if show_progress {
+                        report_progress(progress)
                     }
-                };
-                world.push(Box::new(Sphere::new(&center, 0.2, material)));
+                },
+            )?;
+            if show_progress {
+                eprintln!();
+            }
+            eprintln!("rendered in {:.2?}", start.elapsed());
+            output::write_image_with(&output, &framebuffer.to_image(), &output_args.options())?;
+        }
+        Command::Worker {
+            coordinator,
+            threads,
+        } => {
+            configure_threads(threads)?;
+            distributed::work(&coordinator)?;
+        }
+        Command::Preview { scene, settings } => {
+            configure_threads(settings.threads)?;
+            let scene = Scene::load_with(&scene, &settings.load_options())?;
+            let render_settings = settings.render_settings(&scene);
+            preview::run(scene, render_settings)?;
+        }
+        Command::Bench {
+            scene,
+            runs,
+            settings,
+        } => {
+            configure_threads(settings.threads)?;
+            bench(&scene, runs, &settings)?;
+        }
+        Command::Validate { scenes } => {
+            let mut failed = 0;
+            for path in &scenes {
+                match Scene::load(path) {
+                    Ok(scene) => println!(
+                        "{}: ok ({} objects, {} lights, {}x{}, {} spp)",
+                        path.display(),
+                        scene.object_count,
+                        scene.lights.lights.len(),
+                        scene.camera.image_width,
+                        scene.camera.image_height,
+                        scene.settings.samples_per_pixel
+                    ),
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(format!("{} of {} scenes are invalid", failed, scenes.len()).into());
             }
         }
     }
-    let material1 = Material::Dielectric {
-        refraction_index: 0.5,
-    };
-    world.push(Box::new(Sphere::new(
-        &Vec3::new(0.0, 1., 0.),
-        1.,
-        material1,
-    )));
-
-    let material2 = Material::Lambertian {
-        albedo: Vec3::new(0.4, 0.2, 0.1),
-    };
-    world.push(Box::new(Sphere::new(
-        &Vec3::new(-4.0, 1., 0.),
-        1.,
-        material2,
-    )));
-
-    let material3 = Material::Metal {
-        albedo: Vec3::new(0.7, 0.6, 0.5),
-        fuzz: 0.0,
-    };
-
-    world.push(Box::new(Sphere::new(
-        &Vec3::new(4.0, 1., 0.),
-        1.,
-        material3,
-    )));
+    Ok(())
+}
 
-    let camera: Camera = Camera::new(
-        ASPECT_RATIO,
-        IMAGE_WIDTH,
-        Vec3::new(13., 2., 3.),
-        Vec3::new(0., 0., 0.),
-        20.,
-        0.6,
-        10.,
+fn report_progress(progress: &Progress) {
+    let fraction = progress.fraction();
+    let remaining = progress.elapsed.mul_f64((1.0 - fraction) / fraction);
+    eprint!(
+        "\r{:5.1}% ({}/{} tiles), {:.0?} left    ",
+        fraction * 100.0,
+        progress.tiles_done,
+        progress.tiles_total,
+        remaining
     );
-    let buff_data = Camera::render(Arc::new(camera), world);
+}
 
-    if output == "ppm" {
-        print_image(&buff_data).unwrap();
-        return;
+/// Fails for outputs the image can't be saved to, before rendering rather
+/// than after.
+fn check_output_format<'a>(
+    paths: impl IntoIterator<Item = &'a PathBuf>,
+) -> Result<(), OutputError> {
+    for path in paths {
+        if ImageFormat::from_path(path).is_none() {
+            return Err(OutputError::UnsupportedFormat { path: path.clone() });
+        }
     }
+    Ok(())
+}
 
-    let intensity = Interval::new_with_values(0.0000, 0.9999);
+fn configure_threads(threads: Option<usize>) -> Result<(), Box<dyn Error>> {
+    if let Some(threads) = threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
+    Ok(())
+}
 
-    // sdl things
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("rust-sdl2 demo", IMAGE_WIDTH as u32, IMAGE_HEIGHT as u32)
-        .position_centered()
-        .build()
-        .unwrap();
-    let mut canvas = window.into_canvas().build().unwrap();
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-    canvas.present();
-    let mut event_pump = sdl_context.event_pump().unwrap();
+fn bench(path: &Path, runs: usize, settings: &RenderArgs) -> Result<(), Box<dyn Error>> {
+    let mut times = Vec::with_capacity(runs);
+    for run in 1..=runs {
+        let start = Instant::now();
+        let scene = Scene::load_with(path, &settings.load_options())?;
+        let build_time = start.elapsed();
 
-    'running: loop {
-        canvas.clear();
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => break 'running,
-                _ => {}
-            }
-        }
-        for (j, row) in buff_data.iter().enumerate() {
-            for (i, data) in row.iter().enumerate() {
-                canvas.set_draw_color(Color::RGB(
-                    (256.0 * intensity.clamp(data.x)) as u8,
-                    (256.0 * intensity.clamp(data.y)) as u8,
-                    (256.0 * intensity.clamp(data.z)) as u8,
-                ));
-                let _ = canvas.draw_point(Point::new(i as i32, j as i32));
-            }
-        }
-        canvas.present();
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        let start = Instant::now();
+        let camera = &scene.camera;
+        let samples = render_with(
+            &scene,
+            &settings.render_settings(&scene),
+            Framebuffer::new(camera.image_width, camera.image_height),
+            &RenderHooks::default(),
+        )
+        .total_samples();
+        let render_time = start.elapsed().as_secs_f64();
+        println!(
+            "run {}: build {:.2?}, render {:.3}s ({:.2} M samples/s)",
+            run,
+            build_time,
+            render_time,
+            samples as f64 / render_time / 1e6
+        );
+        times.push(render_time);
     }
+    let mean = times.iter().sum::<f64>() / runs as f64;
+    let min = times.iter().cloned().fold(f64::INFINITY, f64::min);
+    println!(
+        "render mean {:.3}s, min {:.3}s over {} runs",
+        mean, min, runs
+    );
+    Ok(())
 }
@@ -1,11 +1,70 @@
-use crate::{hit_record::HitRecord, ray::Ray, vec3::Vec3};
-use rand::Rng;
+use crate::{
+    hit_record::HitRecord,
+    medium::Medium,
+    microfacet::{self, Frame, Ggx},
+    principled::Principled,
+    ray::Ray,
+    sampler::{self, Sampler},
+    spectrum::{self, Dispersion, Illuminant},
+    texture::Texture,
+    vec3::Vec3,
+};
+use std::{f64::consts::PI, sync::Arc};
 
-#[derive(Clone, Copy)]
+pub struct ScatterRecord {
+    pub attenuation: Vec3,
+    pub scattered: Ray,
+    /// Solid-angle density of `scattered`. Meaningless for specular bounces.
+    pub pdf: f64,
+    /// Set for perfect mirror/glass and other lobes that `eval` and `pdf`
+    /// cannot describe; these are never combined with light sampling.
+    pub is_specular: bool,
+}
+
+#[derive(Clone)]
 pub enum Material {
-    Metal { albedo: Vec3, fuzz: f64 },
-    Lambertian { albedo: Vec3 },
-    Dielectric { refraction_index: f64 },
+    Metal {
+        albedo: Texture,
+        fuzz: f64,
+    },
+    Lambertian {
+        albedo: Texture,
+    },
+    /// Glass. Light inside it is absorbed at rate `absorption` per unit of
+    /// distance in each channel, and with `dispersion` each path follows
+    /// one wavelength through it, with `refraction_index` only used without.
+    Dielectric {
+        refraction_index: f64,
+        absorption: Vec3,
+        dispersion: Option<Dispersion>,
+    },
+    /// Gives off `illuminant` tinted by `emit`.
+    DiffuseLight {
+        emit: Vec3,
+        illuminant: Illuminant,
+    },
+    /// Metal with GGX microfacets and complex index of refraction
+    /// `eta + ik`, see `microfacet::Metal` for measured values.
+    Conductor {
+        eta: Vec3,
+        k: Vec3,
+        microfacet: Ggx,
+    },
+    /// Frosted glass: GGX microfacets that both reflect and refract. Like
+    /// `Dielectric`, radiance isn't scaled by the squared index ratio when
+    /// crossing the surface, which cancels out for closed objects anyway.
+    RoughDielectric {
+        refraction_index: f64,
+        microfacet: Ggx,
+        absorption: Vec3,
+    },
+    /// Layered material with metallic, specular, sheen, clear coat and
+    /// transmission parameters, for assets made in other tools.
+    Principled(Arc<Principled>),
+    /// Not a surface: objects made of it bound a participating medium that
+    /// rays pass into, and scattering is by the medium's phase function.
+    /// See `medium::ConstantMedium`.
+    Medium(Medium),
 }
 impl Material {
     pub fn reflectance(ri: f64, cosine: f64) -> f64 {
@@ -13,42 +72,162 @@ impl Material {
         let r0 = r0 * r0;
         r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
     }
+
+    /// Radiance given off at the hit point, independent of any scattering,
+    /// in the colors of `ray_in`.
+    pub fn emitted(&self, ray_in: &Ray, _hit_record: &HitRecord) -> Vec3 {
+        match self {
+            Material::DiffuseLight { emit, illuminant } => match ray_in.wavelengths {
+                Some(wavelengths) => wavelengths.emission(emit, illuminant),
+                None => Vec3::mul_vec(emit, &illuminant.rgb()),
+            },
+            _ => Vec3::default(),
+        }
+    }
+
+    /// BSDF times the cosine term for light arriving from direction `wi`.
+    /// Zero for specular materials.
+    pub fn eval(&self, ray_in: &Ray, hit_record: &HitRecord, wi: &Vec3) -> Vec3 {
+        match self {
+            Material::Lambertian { albedo } => {
+                let cosine = Vec3::dot(&hit_record.normal, &Vec3::unit(wi)).max(0.0);
+                let albedo = albedo.value(hit_record.u, hit_record.v, &hit_record.point);
+                Vec3::mul(&albedo, cosine / PI)
+            }
+            Material::Conductor { eta, k, microfacet } if !microfacet.is_smooth() => {
+                let (_, wo, wi) = local_directions(ray_in, hit_record, wi);
+                if wo.z <= 0.0 || wi.z <= 0.0 {
+                    return Vec3::default();
+                }
+                let m = Vec3::unit(&Vec3::add(&wo, &wi));
+                let fresnel = microfacet::fresnel_conductor(Vec3::dot(&wo, &m), eta, k);
+                Vec3::mul(
+                    &fresnel,
+                    microfacet.d(&m) * microfacet.g(&wo, &wi) / (4.0 * wo.z),
+                )
+            }
+            Material::RoughDielectric {
+                refraction_index,
+                microfacet,
+                absorption,
+            } if !microfacet.is_smooth() => {
+                let (_, wo, wi) = local_directions(ray_in, hit_record, wi);
+                let eta = relative_ior(*refraction_index, hit_record);
+                let (value, _) = rough_dielectric(microfacet, eta, &wo, &wi);
+                Vec3::mul(&transmittance(absorption, ray_in, hit_record), value)
+            }
+            Material::Principled(principled) => {
+                let (_, wo, wi) = local_directions(ray_in, hit_record, wi);
+                principled.at(hit_record).eval(&wo, &wi).0
+            }
+            Material::Medium(medium) => {
+                Vec3::mul(&medium.albedo, medium.phase.eval(&ray_in.direction, wi))
+            }
+            _ => Vec3::default(),
+        }
+    }
+
+    /// Density with which `scatter` would pick direction `wi`.
+    pub fn pdf(&self, ray_in: &Ray, hit_record: &HitRecord, wi: &Vec3) -> f64 {
+        match self {
+            Material::Lambertian { .. } => {
+                Vec3::dot(&hit_record.normal, &Vec3::unit(wi)).max(0.0) / PI
+            }
+            Material::Conductor { microfacet, .. } if !microfacet.is_smooth() => {
+                let (_, wo, wi) = local_directions(ray_in, hit_record, wi);
+                if wo.z <= 0.0 || wi.z <= 0.0 {
+                    return 0.0;
+                }
+                let m = Vec3::unit(&Vec3::add(&wo, &wi));
+                microfacet.visible_pdf(&wo, &m) / (4.0 * Vec3::dot(&wo, &m))
+            }
+            Material::RoughDielectric {
+                refraction_index,
+                microfacet,
+                ..
+            } if !microfacet.is_smooth() => {
+                let (_, wo, wi) = local_directions(ray_in, hit_record, wi);
+                let eta = relative_ior(*refraction_index, hit_record);
+                rough_dielectric(microfacet, eta, &wo, &wi).1
+            }
+            Material::Principled(principled) => {
+                let (_, wo, wi) = local_directions(ray_in, hit_record, wi);
+                principled.at(hit_record).eval(&wo, &wi).1
+            }
+            Material::Medium(medium) => medium.phase.eval(&ray_in.direction, wi),
+            _ => 0.0,
+        }
+    }
+
+    /// Samples an outgoing ray, or returns `None` if the path ends here.
+    /// `attenuation` is the BSDF times cosine divided by `pdf`.
     pub fn scatter(
         &self,
         ray_in: &Ray,
         hit_record: &HitRecord,
-        attenuation: &mut Vec3,
-        ray: &mut Ray,
-    ) -> bool {
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         match self {
             Material::Lambertian { albedo } => {
-                let mut scatter_dir = Vec3::add(&hit_record.normal, &Vec3::new_rand_unit());
-                if scatter_dir.near_zero() {
-                    scatter_dir = hit_record.normal;
-                }
-                *ray = Ray::new(&hit_record.point, &scatter_dir);
-
-                *attenuation = *albedo;
-                true
+                let (u, v, w) = Vec3::orthonormal_basis(&hit_record.normal);
+                let local = sampler::sample_cosine_hemisphere(sampler.next_2d());
+                let scatter_dir = Vec3::add(
+                    &Vec3::add(&Vec3::mul(&u, local.x), &Vec3::mul(&v, local.y)),
+                    &Vec3::mul(&w, local.z),
+                );
+                let scattered = Ray::new(&hit_record.point, &scatter_dir);
+                Some(ScatterRecord {
+                    attenuation: albedo.value(hit_record.u, hit_record.v, &hit_record.point),
+                    pdf: self.pdf(ray_in, hit_record, &scattered.direction),
+                    scattered,
+                    is_specular: false,
+                })
             }
             Material::Metal { albedo, fuzz } => {
                 let reflected = Vec3::reflect(&ray_in.direction, &hit_record.normal);
                 let reflected = Vec3::add(
                     &Vec3::unit(&reflected),
-                    &Vec3::mul(&Vec3::new_rand_unit(), *fuzz),
+                    &Vec3::mul(&sampler::sample_sphere(sampler.next_2d()), *fuzz),
                 );
-                *ray = Ray::new(&hit_record.point, &reflected);
-                *attenuation = *albedo;
-                Vec3::dot(&ray.direction, &hit_record.normal) > 0.0
+                if Vec3::dot(&reflected, &hit_record.normal) <= 0.0 {
+                    return None;
+                }
+                Some(ScatterRecord {
+                    attenuation: albedo.value(hit_record.u, hit_record.v, &hit_record.point),
+                    scattered: Ray::new(&hit_record.point, &reflected),
+                    pdf: 0.0,
+                    is_specular: true,
+                })
             }
-            Material::Dielectric { refraction_index } => {
-                *attenuation = Vec3::new(1., 1., 1.);
+            Material::Dielectric {
+                refraction_index,
+                absorption,
+                dispersion,
+            } => {
+                let mut attenuation = transmittance(absorption, ray_in, hit_record);
+                // when rendering spectrally the path has wavelengths already
+                let wavelength = ray_in
+                    .wavelength
+                    .or(ray_in.wavelengths.map(|wavelengths| wavelengths.hero()));
+                let (refraction_index, wavelength) = match (dispersion, wavelength) {
+                    (None, _) => (*refraction_index, ray_in.wavelength),
+                    (Some(dispersion), Some(wavelength)) => {
+                        (dispersion.refraction_index(wavelength), Some(wavelength))
+                    }
+                    (Some(dispersion), None) => {
+                        // white light splits up here, so the path picks one
+                        // of the colors to follow
+                        let wavelength = spectrum::sample_wavelength(sampler.next_1d());
+                        attenuation =
+                            Vec3::mul_vec(&attenuation, &spectrum::rgb_weight(wavelength));
+                        (dispersion.refraction_index(wavelength), Some(wavelength))
+                    }
+                };
                 let ri = if hit_record.front_face {
                     1.0 / refraction_index
                 } else {
-                    *refraction_index
+                    refraction_index
                 };
-                let mut rng = rand::thread_rng();
                 let unit_dir = Vec3::unit(&ray_in.direction);
                 let cos_theta = Vec3::dot(&Vec3::mul(&unit_dir, -1.0), &hit_record.normal).min(1.0);
                 let sin_theta = (1.0 - (cos_theta * cos_theta)).sqrt();
@@ -56,13 +235,193 @@ impl Material {
 
                 let mut refracted = Vec3::refract(&unit_dir, &hit_record.normal, ri);
                 if cannot_refract
-                    || Self::reflectance(*refraction_index, cos_theta) > rng.gen::<f64>()
+                    || Self::reflectance(refraction_index, cos_theta) > sampler.next_1d()
                 {
                     refracted = Vec3::reflect(&unit_dir, &hit_record.normal);
                 }
-                *ray = Ray::new(&hit_record.point, &refracted);
-                true
+                let mut scattered = Ray::new(&hit_record.point, &refracted);
+                scattered.wavelength = wavelength;
+                Some(ScatterRecord {
+                    attenuation,
+                    scattered,
+                    pdf: 0.0,
+                    is_specular: true,
+                })
+            }
+            Material::Conductor { eta, k, microfacet } => {
+                let (frame, wo, _) = local_directions(ray_in, hit_record, &Vec3::default());
+                if wo.z <= 0.0 {
+                    return None;
+                }
+                if microfacet.is_smooth() {
+                    let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+                    return Some(ScatterRecord {
+                        attenuation: microfacet::fresnel_conductor(wo.z, eta, k),
+                        scattered: Ray::new(&hit_record.point, &frame.to_world(&wi)),
+                        pdf: 0.0,
+                        is_specular: true,
+                    });
+                }
+                let m = microfacet.sample_visible_normal(&wo, sampler.next_2d());
+                let wi = microfacet::reflect(&wo, &m);
+                if wi.z <= 0.0 {
+                    return None;
+                }
+                let cos_o = Vec3::dot(&wo, &m);
+                Some(ScatterRecord {
+                    attenuation: Vec3::mul(
+                        &microfacet::fresnel_conductor(cos_o, eta, k),
+                        microfacet.g(&wo, &wi) / microfacet.g1(&wo),
+                    ),
+                    scattered: Ray::new(&hit_record.point, &frame.to_world(&wi)),
+                    pdf: microfacet.visible_pdf(&wo, &m) / (4.0 * cos_o),
+                    is_specular: false,
+                })
+            }
+            Material::RoughDielectric {
+                refraction_index,
+                microfacet,
+                absorption,
+            } => {
+                let (frame, wo, _) = local_directions(ray_in, hit_record, &Vec3::default());
+                if wo.z <= 0.0 {
+                    return None;
+                }
+                let eta = relative_ior(*refraction_index, hit_record);
+                let smooth = microfacet.is_smooth();
+                let m = if smooth {
+                    Vec3::new(0.0, 0.0, 1.0)
+                } else {
+                    microfacet.sample_visible_normal(&wo, sampler.next_2d())
+                };
+                let fresnel = microfacet::fresnel_dielectric(Vec3::dot(&wo, &m), eta);
+                let refracted =
+                    microfacet::refract(&wo, &m, eta).filter(|_| sampler.next_1d() >= fresnel);
+                let reflected = refracted.is_none();
+                let wi = refracted.unwrap_or_else(|| microfacet::reflect(&wo, &m));
+                // off a steep microfacet the ray can end up on the other side
+                // of the surface, where `rough_dielectric` would take it for
+                // the other lobe
+                if (wi.z > 0.0) != reflected {
+                    return None;
+                }
+                let scattered = Ray::new(&hit_record.point, &frame.to_world(&wi));
+                let transmittance = transmittance(absorption, ray_in, hit_record);
+                if smooth {
+                    return Some(ScatterRecord {
+                        attenuation: transmittance,
+                        scattered,
+                        pdf: 0.0,
+                        is_specular: true,
+                    });
+                }
+                let (value, pdf) = rough_dielectric(microfacet, eta, &wo, &wi);
+                if pdf <= 0.0 {
+                    return None;
+                }
+                Some(ScatterRecord {
+                    attenuation: Vec3::mul(&transmittance, value / pdf),
+                    scattered,
+                    pdf,
+                    is_specular: false,
+                })
+            }
+            Material::Principled(principled) => {
+                let (frame, wo, _) = local_directions(ray_in, hit_record, &Vec3::default());
+                let (wi, value, pdf) = principled.at(hit_record).sample(&wo, sampler)?;
+                Some(ScatterRecord {
+                    attenuation: Vec3::div(&value, pdf),
+                    scattered: Ray::new(&hit_record.point, &frame.to_world(&wi)),
+                    pdf,
+                    is_specular: false,
+                })
+            }
+            Material::Medium(medium) => {
+                let direction = medium.phase.sample(&ray_in.direction, sampler.next_2d());
+                Some(ScatterRecord {
+                    attenuation: medium.albedo,
+                    pdf: medium.phase.eval(&ray_in.direction, &direction),
+                    scattered: Ray::new(&hit_record.point, &direction),
+                    is_specular: false,
+                })
             }
+            Material::DiffuseLight { .. } => None,
         }
     }
 }
+
+/// The shading frame at the hit, with the direction towards where `ray_in`
+/// came from and `wi` in it.
+fn local_directions(ray_in: &Ray, hit_record: &HitRecord, wi: &Vec3) -> (Frame, Vec3, Vec3) {
+    let frame = Frame::new(&hit_record.normal);
+    let wo = frame.to_local(&Vec3::unit(&Vec3::mul(&ray_in.direction, -1.0)));
+    let wi = frame.to_local(&Vec3::unit(wi));
+    (frame, wo, wi)
+}
+
+/// Beer-Lambert attenuation of light that crossed the inside of a material
+/// absorbing at rate `absorption` to reach a hit on its back.
+fn transmittance(absorption: &Vec3, ray_in: &Ray, hit_record: &HitRecord) -> Vec3 {
+    if hit_record.front_face {
+        return Vec3::new(1.0, 1.0, 1.0);
+    }
+    let distance = hit_record.t * ray_in.direction.length();
+    Vec3::new(
+        (-absorption.x * distance).exp(),
+        (-absorption.y * distance).exp(),
+        (-absorption.z * distance).exp(),
+    )
+}
+
+/// Index of refraction of the side the normal points away from over the
+/// side it points to.
+fn relative_ior(refraction_index: f64, hit_record: &HitRecord) -> f64 {
+    if hit_record.front_face {
+        refraction_index
+    } else {
+        1.0 / refraction_index
+    }
+}
+
+/// BSDF times cosine of rough glass and its sampling density, for local
+/// directions with `wo` on the side of the normal (Walter et al. 2007).
+fn rough_dielectric(microfacet: &Ggx, eta: f64, wo: &Vec3, wi: &Vec3) -> (f64, f64) {
+    if wo.z <= 0.0 || wi.z == 0.0 {
+        return (0.0, 0.0);
+    }
+    let reflect = wi.z > 0.0;
+    let m = if reflect {
+        Vec3::add(wo, wi)
+    } else {
+        Vec3::mul(&Vec3::add(wo, &Vec3::mul(wi, eta)), -1.0)
+    };
+    if m.near_zero() {
+        return (0.0, 0.0);
+    }
+    let m = Vec3::unit(&m);
+    let m = if m.z < 0.0 { Vec3::mul(&m, -1.0) } else { m };
+    let (cos_o, cos_i) = (Vec3::dot(wo, &m), Vec3::dot(wi, &m));
+    // both directions have to be on the right side of the microfacet too
+    if cos_o <= 0.0 || (cos_i > 0.0) != reflect {
+        return (0.0, 0.0);
+    }
+
+    let fresnel = microfacet::fresnel_dielectric(cos_o, eta);
+    let d = microfacet.d(&m);
+    let g = microfacet.g(wo, wi);
+    let visible = microfacet.visible_pdf(wo, &m);
+    if reflect {
+        (
+            fresnel * d * g / (4.0 * wo.z),
+            fresnel * visible / (4.0 * cos_o),
+        )
+    } else {
+        // change of variables from the microfacet normal to `wi`
+        let denominator = cos_o + eta * cos_i;
+        let jacobian = eta * eta * cos_i.abs() / (denominator * denominator);
+        (
+            (1.0 - fresnel) * d * g * cos_o * jacobian / wo.z,
+            (1.0 - fresnel) * visible * jacobian,
+        )
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{
+    aabb::Aabb,
+    hit_record::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    vec3::Vec3,
+};
+use std::f64::consts::PI;
+
+// Participating media like smoke and fog are clouds of tiny particles. Light
+// going through one runs into a particle after a random distance, which is
+// shorter the denser the medium, and is then either absorbed or scattered in
+// a new direction.
+
+/// How light scattered by a particle spreads over directions.
+#[derive(Clone, Copy, Debug)]
+pub enum PhaseFunction {
+    /// Evenly in all directions.
+    Isotropic,
+    /// Mostly onwards for `g` towards 1, like haze and clouds, mostly back
+    /// for `g` towards -1, and evenly for 0.
+    HenyeyGreenstein { g: f64 },
+}
+
+impl PhaseFunction {
+    /// Density over directions of light that travelled along `incoming`
+    /// going on along `outgoing`, which is also how likely `sample` is to
+    /// pick it.
+    pub fn eval(&self, incoming: &Vec3, outgoing: &Vec3) -> f64 {
+        match self {
+            PhaseFunction::Isotropic => 1.0 / (4.0 * PI),
+            PhaseFunction::HenyeyGreenstein { g } => {
+                let cos_theta = Vec3::dot(&Vec3::unit(incoming), &Vec3::unit(outgoing));
+                let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+                (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+            }
+        }
+    }
+
+    /// A direction for light that travelled along `incoming` to go on in.
+    pub fn sample(&self, incoming: &Vec3, u: (f64, f64)) -> Vec3 {
+        let cos_theta = match self {
+            PhaseFunction::HenyeyGreenstein { g } if g.abs() > 1e-3 => {
+                let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u.0);
+                ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+            }
+            _ => 1.0 - 2.0 * u.0,
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let (u, v, w) = Vec3::orthonormal_basis(&Vec3::unit(incoming));
+        Vec3::add(
+            &Vec3::add(
+                &Vec3::mul(&u, sin_theta * phi.cos()),
+                &Vec3::mul(&v, sin_theta * phi.sin()),
+            ),
+            &Vec3::mul(&w, cos_theta),
+        )
+    }
+}
+
+/// A medium of the same density everywhere.
+#[derive(Clone, Copy, Debug)]
+pub struct Medium {
+    /// Collisions per unit of distance.
+    pub density: f64,
+    /// The fraction of light scattered rather than absorbed at a collision,
+    /// per channel.
+    pub albedo: Vec3,
+    pub phase: PhaseFunction,
+}
+
+impl Medium {
+    /// How far light goes before its next collision, for `u` in `0..1`.
+    pub fn sample_distance(&self, u: f64) -> f64 {
+        -(1.0 - u).ln() / self.density
+    }
+
+    /// The fraction of light that gets across `distance` without colliding.
+    pub fn transmittance(&self, distance: f64) -> f64 {
+        (-self.density * distance).exp()
+    }
+}
+
+/// A volume of `medium` in the shape of `boundary`, which has to be closed.
+/// Rays cross the boundary without bending, and volumes shouldn't overlap
+/// or contain the camera.
+pub struct ConstantMedium {
+    boundary: Box<dyn Hittable>,
+    material: Material,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Box<dyn Hittable>, medium: Medium) -> Self {
+        Self {
+            boundary,
+            material: Material::Medium(medium),
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit<'a>(&'a self, ray: &Ray, interval: &Interval, hit_record: &mut HitRecord<'a>) -> bool {
+        if !self.boundary.hit(ray, interval, hit_record) {
+            return false;
+        }
+        hit_record.material = Some(&self.material);
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+}
+
+/// Atmospheric fog filling a ball of `radius` around the camera, beyond
+/// which rays reach the background.
+#[derive(Clone, Copy, Debug)]
+pub struct Fog {
+    pub medium: Medium,
+    pub radius: f64,
+}
+
+impl Fog {
+    /// The part of `ray` between `t_min` and `t_max` that is inside the fog
+    /// around `center`.
+    pub fn segment(&self, center: &Vec3, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let oc = Vec3::sub(&ray.origin, center);
+        let a = ray.direction.length_squared();
+        let h = Vec3::dot(&oc, &ray.direction);
+        let c = oc.length_squared() - self.radius * self.radius;
+        let d = h * h - a * c;
+        if d <= 0.0 {
+            return None;
+        }
+        let sqrtd = d.sqrt();
+        let start = ((-h - sqrtd) / a).max(t_min);
+        let end = ((-h + sqrtd) / a).min(t_max);
+        (start < end).then_some((start, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{render, scene::LoadOptions, Scene};
+    use std::path::Path;
+
+    fn absorbing(density: f64) -> Medium {
+        Medium {
+            density,
+            albedo: Vec3::default(),
+            phase: PhaseFunction::Isotropic,
+        }
+    }
+
+    #[test]
+    fn free_flights_follow_beer_lambert() {
+        const SAMPLES: usize = 100000;
+        for density in [0.3, 1.0, 4.0] {
+            let medium = absorbing(density);
+            for distance in [0.1, 0.5, 1.0] {
+                let passed = (0..SAMPLES)
+                    .filter(|&i| {
+                        let u = (i as f64 + 0.5) / SAMPLES as f64;
+                        medium.sample_distance(u) > distance
+                    })
+                    .count();
+                let fraction = passed as f64 / SAMPLES as f64;
+                let expected = (-density * distance).exp();
+                assert_eq!(medium.transmittance(distance), expected);
+                assert!(
+                    (fraction - expected).abs() < 1e-4,
+                    "{fraction} got across {distance} at density {density}, not {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn light_through_a_constant_medium_falls_off_exponentially() {
+        // a white background seen through the middle of a black ball of
+        // smoke, whose diameter is 2
+        for density in [0.25, 1.0, 2.0] {
+            let source = format!(
+                r#"
+                [camera]
+                look_from = [0.0, 0.0, 10.0]
+                look_at = [0.0, 0.0, 0.0]
+                fov = 0.1
+
+                [render]
+                width = 4
+                height = 4
+                samples_per_pixel = 4096
+                max_depth = 8
+                background = [1.0, 1.0, 1.0]
+
+                [[objects]]
+                type = "constant_medium"
+                density = {density}
+                albedo = [0.0, 0.0, 0.0]
+                boundary = {{ type = "sphere", center = [0.0, 0.0, 0.0], radius = 1.0 }}
+                "#
+            );
+            let scene =
+                Scene::parse(&source, Path::new("medium.toml"), &LoadOptions::default()).unwrap();
+            let image = render(&scene, &scene.settings);
+            let mean =
+                image.pixels.iter().map(|pixel| pixel.y).sum::<f64>() / image.pixels.len() as f64;
+
+            let expected = (-density * 2.0_f64).exp();
+            let samples = (image.pixels.len() * 4096) as f64;
+            let error = (expected * (1.0 - expected) / samples).sqrt();
+            assert!(
+                (mean - expected).abs() < 4.0 * error,
+                "{mean} got through at density {density}, not {expected}"
+            );
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+
+// Rough surfaces are modelled as many tiny mirrors whose normals follow the
+// GGX (Trowbridge-Reitz) distribution. Everything here works in a local
+// shading frame where the surface normal is +z; see `Frame`.
+
+/// The GGX distribution of microfacet normals, with separate widths along
+/// the tangent and bitangent for anisotropic surfaces.
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+    /// An isotropic distribution for a perceptual roughness between 0
+    /// (mirror) and 1; the width is its square, which looks more even.
+    pub fn new(roughness: f64) -> Self {
+        Self::anisotropic(roughness, 0.0)
+    }
+
+    /// `anisotropy` between 0 and 1 stretches the highlights along the
+    /// tangent.
+    pub fn anisotropic(roughness: f64, anisotropy: f64) -> Self {
+        let alpha = roughness * roughness;
+        let aspect = (1.0 - 0.9 * anisotropy).sqrt();
+        Self {
+            alpha_x: (alpha / aspect).max(1e-4),
+            alpha_y: (alpha * aspect).max(1e-4),
+        }
+    }
+
+    /// So close to a mirror that it is better treated as one: sampling is
+    /// exact then, and the density would be too peaked to evaluate.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    /// Density of microfacet normal `m` per unit projected area.
+    pub fn d(&self, m: &Vec3) -> f64 {
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+        let e = (m.x / self.alpha_x).powi(2) + (m.y / self.alpha_y).powi(2) + m.z * m.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f64 {
+        if w.z == 0.0 {
+            return f64::INFINITY;
+        }
+        let a2 = ((self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2)) / (w.z * w.z);
+        0.5 * ((1.0 + a2).sqrt() - 1.0)
+    }
+
+    /// Smith masking: the fraction of microfacets facing `w` that `w` sees.
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height-correlated Smith shadowing-masking for the pair `wo`, `wi`.
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of `sample_visible_normal` picking `m` as seen from `wo`.
+    pub fn visible_pdf(&self, wo: &Vec3, m: &Vec3) -> f64 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * Vec3::dot(wo, m).max(0.0) * self.d(m) / wo.z
+    }
+
+    /// Samples a microfacet normal in proportion to how much of it `wo`
+    /// sees (Heitz 2018), which wastes far fewer samples on facets facing
+    /// away than sampling `d` would.
+    pub fn sample_visible_normal(&self, wo: &Vec3, u: (f64, f64)) -> Vec3 {
+        // stretch the view so the distribution becomes a hemisphere
+        let v = Vec3::unit(&Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z));
+        let length_squared = v.x * v.x + v.y * v.y;
+        let t1 = if length_squared > 0.0 {
+            Vec3::div(&Vec3::new(-v.y, v.x, 0.0), length_squared.sqrt())
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vec3::cross(&v, &t1);
+
+        // a point on the projected hemisphere, half of which is squashed
+        // by the view angle
+        let r = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + v.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let p3 = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        let n = Vec3::add(
+            &Vec3::add(&Vec3::mul(&t1, p1), &Vec3::mul(&t2, p2)),
+            &Vec3::mul(&v, p3),
+        );
+
+        // and unstretch the normal
+        Vec3::unit(&Vec3::new(
+            self.alpha_x * n.x,
+            self.alpha_y * n.y,
+            n.z.max(1e-6),
+        ))
+    }
+}
+
+/// An orthonormal basis around a surface normal, to move directions in and
+/// out of the local shading frame.
+pub struct Frame {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Frame {
+    pub fn new(normal: &Vec3) -> Self {
+        let (u, v, w) = Vec3::orthonormal_basis(normal);
+        Self { u, v, w }
+    }
+
+    pub fn to_local(&self, d: &Vec3) -> Vec3 {
+        Vec3::new(
+            Vec3::dot(d, &self.u),
+            Vec3::dot(d, &self.v),
+            Vec3::dot(d, &self.w),
+        )
+    }
+
+    pub fn to_world(&self, d: &Vec3) -> Vec3 {
+        Vec3::add(
+            &Vec3::add(&Vec3::mul(&self.u, d.x), &Vec3::mul(&self.v, d.y)),
+            &Vec3::mul(&self.w, d.z),
+        )
+    }
+}
+
+/// Mirror image of `wo` about `m`.
+pub fn reflect(wo: &Vec3, m: &Vec3) -> Vec3 {
+    Vec3::sub(&Vec3::mul(m, 2.0 * Vec3::dot(wo, m)), wo)
+}
+
+/// Direction `wo` is bent into when crossing a surface with normal `m` into
+/// a medium `eta` times as dense, or `None` on total internal reflection.
+pub fn refract(wo: &Vec3, m: &Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = Vec3::dot(wo, m);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(Vec3::sub(
+        &Vec3::mul(m, cos_i / eta - cos_t),
+        &Vec3::div(wo, eta),
+    ))
+}
+
+/// Fraction of unpolarized light reflected when arriving at angle `cos_i`
+/// to the normal on a dielectric `eta` times as dense as where it comes
+/// from.
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+/// Reflectance of a conductor with complex index of refraction `eta + ik`,
+/// per color channel.
+pub fn fresnel_conductor(cos_i: f64, eta: &Vec3, k: &Vec3) -> Vec3 {
+    let channel = |eta: f64, k: f64| {
+        let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+        let sin2 = 1.0 - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_i.clamp(0.0, 1.0) * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rs + rp)
+    };
+    Vec3::new(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+/// Measured complex indices of refraction of common metals, at the red,
+/// green and blue wavelengths.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metal {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl Metal {
+    /// `(eta, k)`.
+    pub fn ior(&self) -> (Vec3, Vec3) {
+        match self {
+            Metal::Gold => (
+                Vec3::new(0.143, 0.374, 1.442),
+                Vec3::new(3.983, 2.385, 1.603),
+            ),
+            Metal::Copper => (
+                Vec3::new(0.200, 0.924, 1.102),
+                Vec3::new(3.912, 2.452, 2.142),
+            ),
+            Metal::Aluminium => (
+                Vec3::new(1.657, 0.880, 0.521),
+                Vec3::new(9.224, 6.270, 4.837),
+            ),
+            Metal::Silver => (
+                Vec3::new(0.155, 0.117, 0.138),
+                Vec3::new(4.828, 3.122, 2.147),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distributions() -> [Ggx; 4] {
+        [
+            Ggx::new(0.3),
+            Ggx::new(0.6),
+            Ggx::new(1.0),
+            Ggx::anisotropic(0.5, 0.8),
+        ]
+    }
+
+    /// Directions all over the upper hemisphere, down to nearly grazing.
+    fn directions() -> Vec<Vec3> {
+        let mut directions = Vec::new();
+        for cos in [1.0_f64, 0.7, 0.3, 0.05] {
+            for phi in [0.0, 1.0, 2.5, 4.0] {
+                let sin = (1.0 - cos * cos).sqrt();
+                directions.push(Vec3::new(sin * f64::cos(phi), sin * f64::sin(phi), cos));
+            }
+        }
+        directions
+    }
+
+    #[test]
+    fn visible_normal_density_integrates_to_one() {
+        // midpoint rule over s with cos θ = 1 - s², which puts the points
+        // close together near the normal where the density peaks
+        let (steps_s, steps_phi) = (1000, 256);
+        for ggx in distributions() {
+            for wo in directions() {
+                let mut sum = 0.0;
+                for i in 0..steps_s {
+                    let s = (i as f64 + 0.5) / steps_s as f64;
+                    let cos = 1.0 - s * s;
+                    let sin = (1.0 - cos * cos).sqrt();
+                    for j in 0..steps_phi {
+                        let phi = 2.0 * PI * (j as f64 + 0.5) / steps_phi as f64;
+                        let m = Vec3::new(sin * phi.cos(), sin * phi.sin(), cos);
+                        sum += ggx.visible_pdf(&wo, &m) * 2.0 * s;
+                    }
+                }
+                let integral = sum * 2.0 * PI / (steps_s * steps_phi) as f64;
+                assert!(
+                    (integral - 1.0).abs() < 1e-3,
+                    "{integral} for {ggx:?} from {wo:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn masking_never_exceeds_one() {
+        for ggx in distributions() {
+            for wo in directions() {
+                for wi in directions() {
+                    let g = ggx.g(&wo, &wi);
+                    assert!(
+                        (0.0..=1.0).contains(&g) && g <= ggx.g1(&wo) && g <= ggx.g1(&wi),
+                        "g = {g} for {ggx:?} between {wo:?} and {wi:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn conductor_fresnel_at_normal_incidence() {
+        for metal in [Metal::Gold, Metal::Copper, Metal::Aluminium, Metal::Silver] {
+            let (eta, k) = metal.ior();
+            let reflectance = fresnel_conductor(1.0, &eta, &k);
+            for (reflectance, eta, k) in [
+                (reflectance.x, eta.x, k.x),
+                (reflectance.y, eta.y, k.y),
+                (reflectance.z, eta.z, k.z),
+            ] {
+                let expected = ((eta - 1.0).powi(2) + k * k) / ((eta + 1.0).powi(2) + k * k);
+                assert!(
+                    (reflectance - expected).abs() < 1e-9,
+                    "{metal:?}: {reflectance} instead of {expected}"
+                );
+            }
+        }
+        // glass, as a conductor without absorption
+        let glass = fresnel_conductor(1.0, &Vec3::new(1.5, 1.5, 1.5), &Vec3::default());
+        assert!((glass.x - 0.04).abs() < 1e-9, "{glass:?}");
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,674 @@
+use crate::{
+    hittable_list::HittableList,
+    material::Material,
+    principled::Principled,
+    texture::{ImageTexture, Texture},
+    triangle_mesh::TriangleMesh,
+    vec3::Vec3,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+/// Material used for faces that come before any `usemtl`.
+pub const DEFAULT_MATERIAL: Material = Material::Lambertian {
+    albedo: Texture::Solid(Vec3 {
+        x: 0.8,
+        y: 0.8,
+        z: 0.8,
+    }),
+};
+
+struct MtlEntry {
+    name: String,
+    kd: Vec3,
+    ks: Vec3,
+    ns: f64,
+    ni: f64,
+    d: f64,
+    illum: u32,
+    map_kd: Option<Arc<ImageTexture>>,
+    /// The physically based extension (`Pr`, `Pm`, `Ps`, `Pc`, `Pcr`,
+    /// `aniso`); any of them makes the entry a `Principled` material.
+    pbr: Option<PbrEntry>,
+}
+
+#[derive(Default)]
+struct PbrEntry {
+    roughness: Option<f64>,
+    metallic: Option<f64>,
+    sheen: Option<f64>,
+    clearcoat: Option<f64>,
+    clearcoat_roughness: Option<f64>,
+    anisotropy: Option<f64>,
+}
+
+impl MtlEntry {
+    /// Translates the entry to the closest `Material` variant: entries with
+    /// physically based parameters become `Principled`, transparent or
+    /// refractive illumination models `Dielectric`, reflective ones `Metal`
+    /// (with `Ns` mapped to fuzz), everything else `Lambertian`. `map_Kd`
+    /// replaces the diffuse color when present.
+    fn to_material(&self) -> Material {
+        let diffuse = match &self.map_kd {
+            Some(image) => Texture::Image(Arc::clone(image)),
+            None => Texture::Solid(self.kd),
+        };
+        if let Some(pbr) = &self.pbr {
+            let defaults = Principled::default();
+            let constant = |value: Option<f64>, default| match value {
+                Some(value) => Texture::Solid(Vec3::new(value, value, value)),
+                None => default,
+            };
+            let transparency = 1.0 - self.d.clamp(0.0, 1.0);
+            return Material::Principled(Arc::new(Principled {
+                base_color: diffuse,
+                metallic: constant(pbr.metallic, defaults.metallic),
+                roughness: constant(pbr.roughness, defaults.roughness),
+                sheen: constant(pbr.sheen, defaults.sheen),
+                clearcoat: constant(pbr.clearcoat, defaults.clearcoat),
+                clearcoat_roughness: constant(
+                    pbr.clearcoat_roughness,
+                    defaults.clearcoat_roughness,
+                ),
+                transmission: Texture::Solid(Vec3::new(transparency, transparency, transparency)),
+                ior: Texture::Solid(Vec3::new(self.ni, self.ni, self.ni)),
+                anisotropy: constant(pbr.anisotropy, defaults.anisotropy),
+                ..defaults
+            }));
+        }
+        if self.d < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            Material::Dielectric {
+                refraction_index: self.ni,
+                absorption: Vec3::default(),
+                dispersion: None,
+            }
+        } else if matches!(self.illum, 3 | 5 | 8) {
+            // Blinn-Phong exponent to an equivalent roughness
+            let fuzz = (2.0 / (self.ns + 2.0)).sqrt().clamp(0.0, 1.0);
+            let albedo = if self.ks.near_zero() {
+                diffuse
+            } else {
+                Texture::Solid(self.ks)
+            };
+            Material::Metal { albedo, fuzz }
+        } else {
+            Material::Lambertian { albedo: diffuse }
+        }
+    }
+}
+
+pub struct ObjMesh {
+    /// Name from the last `g` or `o` statement, empty if there was none.
+    pub group: String,
+    pub material_name: Option<String>,
+    pub mesh: TriangleMesh,
+}
+
+pub struct ObjModel {
+    pub meshes: Vec<ObjMesh>,
+}
+
+impl ObjModel {
+    /// Loads an OBJ file and any MTL libraries it references, resolved
+    /// relative to the OBJ file. Faces are split into one mesh per group and
+    /// material; n-gons are fan-triangulated. A mesh only gets vertex normals
+    /// (or UVs) if every one of its faces provides them, otherwise it is flat
+    /// shaded.
+    pub fn load(path: &Path) -> Result<Self, ObjError> {
+        let source = read_file(path)?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        Self::parse(&source, path, base_dir, None)
+    }
+
+    /// Like `load`, but every face gets `material` instead of its MTL one,
+    /// and MTL libraries aren't read, so they needn't exist.
+    pub fn load_with_material(path: &Path, material: Material) -> Result<Self, ObjError> {
+        let source = read_file(path)?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        Self::parse(&source, path, base_dir, Some(material))
+    }
+
+    /// Parses OBJ text. `path` is only used in error messages; `mtllib`
+    /// references are looked up in `base_dir`. `material_override`, when
+    /// set, replaces whatever `usemtl` selects, and `mtllib` is skipped.
+    pub fn parse(
+        source: &str,
+        path: &Path,
+        base_dir: &Path,
+        material_override: Option<Material>,
+    ) -> Result<Self, ObjError> {
+        let mut positions: Vec<Vec3> = Vec::new();
+        let mut normals: Vec<Vec3> = Vec::new();
+        let mut uvs: Vec<(f64, f64)> = Vec::new();
+        let mut materials: HashMap<String, Material> = HashMap::new();
+
+        let mut builders: Vec<MeshBuilder> = Vec::new();
+        let mut builder_lookup: HashMap<(String, Option<String>), usize> = HashMap::new();
+        let mut group = String::new();
+        let mut material_name: Option<String> = None;
+
+        for (line_no, line) in logical_lines(source) {
+            let err = |message: String| ObjError::Parse {
+                path: path.to_path_buf(),
+                line: line_no,
+                message,
+            };
+            let mut tokens = line.split_whitespace();
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+            let args: Vec<&str> = tokens.collect();
+
+            match keyword {
+                "v" => positions.push(parse_vec3(&args, keyword).map_err(err)?),
+                "vn" => normals.push(parse_vec3(&args, keyword).map_err(err)?),
+                "vt" => {
+                    if args.is_empty() {
+                        return Err(err("`vt` needs at least one coordinate".to_string()));
+                    }
+                    let u = parse_f64(args[0]).map_err(err)?;
+                    let v = match args.get(1) {
+                        Some(v) => parse_f64(v).map_err(err)?,
+                        None => 0.0,
+                    };
+                    uvs.push((u, v));
+                }
+                "f" => {
+                    if args.len() < 3 {
+                        return Err(err(format!(
+                            "face needs at least 3 vertices, got {}",
+                            args.len()
+                        )));
+                    }
+                    let corners = args
+                        .iter()
+                        .map(|arg| {
+                            parse_face_vertex(arg, positions.len(), uvs.len(), normals.len())
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(err)?;
+
+                    let key = (group.clone(), material_name.clone());
+                    let index = *builder_lookup.entry(key).or_insert_with(|| {
+                        builders.push(MeshBuilder::new(group.clone(), material_name.clone()));
+                        builders.len() - 1
+                    });
+                    builders[index].push_polygon(&corners, &positions, &uvs, &normals);
+                }
+                "g" | "o" => group = args.join(" "),
+                "usemtl" => {
+                    let name = args.join(" ");
+                    if material_override.is_none() && !materials.contains_key(&name) {
+                        return Err(err(format!("unknown material `{}`", name)));
+                    }
+                    material_name = Some(name);
+                }
+                "mtllib" => {
+                    if args.is_empty() {
+                        return Err(err("`mtllib` needs a file name".to_string()));
+                    }
+                    if material_override.is_some() {
+                        continue;
+                    }
+                    for file in args {
+                        let mtl_path = base_dir.join(file);
+                        let source = read_file(&mtl_path)?;
+                        materials.extend(parse_mtl(&source, &mtl_path)?);
+                    }
+                }
+                // smoothing groups, lines, points, free-form geometry and
+                // render attributes that have no meaning here
+                "s" | "l" | "p" | "vp" | "cstype" | "deg" | "bmat" | "step" | "curv" | "curv2"
+                | "surf" | "parm" | "trim" | "hole" | "scrv" | "sp" | "end" | "con" | "mg"
+                | "bevel" | "c_interp" | "d_interp" | "lod" | "usemap" | "maplib"
+                | "shadow_obj" | "trace_obj" | "ctech" | "stech" => {}
+                other => return Err(err(format!("unknown statement `{}`", other))),
+            }
+        }
+
+        let meshes = builders
+            .into_iter()
+            .filter(|builder| !builder.indices.is_empty())
+            .map(|builder| {
+                let material = material_override.clone().unwrap_or_else(|| {
+                    builder
+                        .material_name
+                        .as_ref()
+                        .map_or(DEFAULT_MATERIAL, |name| materials[name].clone())
+                });
+                builder.build(material)
+            })
+            .collect();
+        Ok(Self { meshes })
+    }
+
+    pub fn into_hittable_list(self) -> HittableList {
+        let mut list = HittableList::new();
+        for obj_mesh in self.meshes {
+            list.push(Box::new(obj_mesh.mesh));
+        }
+        list
+    }
+}
+
+/// Parses MTL text into materials keyed by `newmtl` name. Texture maps are
+/// looked up relative to `path`.
+pub fn parse_mtl(source: &str, path: &Path) -> Result<HashMap<String, Material>, ObjError> {
+    fn finish(entry: MtlEntry, materials: &mut HashMap<String, Material>) {
+        materials.insert(entry.name.clone(), entry.to_material());
+    }
+
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut materials = HashMap::new();
+    let mut current: Option<MtlEntry> = None;
+
+    for (line_no, line) in logical_lines(source) {
+        let err = |message: String| ObjError::Parse {
+            path: path.to_path_buf(),
+            line: line_no,
+            message,
+        };
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if args.is_empty() {
+                return Err(err("`newmtl` needs a name".to_string()));
+            }
+            if let Some(entry) = current.take() {
+                finish(entry, &mut materials);
+            }
+            current = Some(MtlEntry {
+                name: args.join(" "),
+                kd: Vec3::new(0.8, 0.8, 0.8),
+                ks: Vec3::default(),
+                ns: 0.0,
+                ni: 1.5,
+                d: 1.0,
+                illum: 2,
+                map_kd: None,
+                pbr: None,
+            });
+            continue;
+        }
+
+        let Some(entry) = current.as_mut() else {
+            return Err(err(format!("`{}` before any `newmtl`", keyword)));
+        };
+        match keyword {
+            "Kd" => entry.kd = parse_color(&args, keyword).map_err(err)?,
+            "Ks" => entry.ks = parse_color(&args, keyword).map_err(err)?,
+            "Ns" => entry.ns = parse_scalar(&args, keyword).map_err(err)?,
+            "Ni" => {
+                let value = parse_scalar(&args, keyword).map_err(err)?;
+                if value <= 0.0 {
+                    return Err(err(format!("`Ni` must be positive, got `{}`", args[0])));
+                }
+                entry.ni = value;
+            }
+            "d" => entry.d = parse_scalar(&args, keyword).map_err(err)?,
+            "Tr" => entry.d = 1.0 - parse_scalar(&args, keyword).map_err(err)?,
+            "illum" => {
+                let value = parse_scalar(&args, keyword).map_err(err)?;
+                if value < 0.0 || value.fract() != 0.0 {
+                    return Err(err(format!("invalid illumination model `{}`", args[0])));
+                }
+                entry.illum = value as u32;
+            }
+            "map_Kd" => {
+                // options like `-s 1 1 1` come first, the file name last
+                let Some(file) = args.last() else {
+                    return Err(err("`map_Kd` needs a file name".to_string()));
+                };
+                let image = ImageTexture::load(&base_dir.join(file))
+                    .map_err(|e| err(format!("cannot load texture `{}`: {}", file, e)))?;
+                entry.map_kd = Some(Arc::new(image));
+            }
+            "Pr" | "Pm" | "Ps" | "Pc" | "Pcr" | "aniso" => {
+                // sheen is sometimes given as a color
+                let value = parse_color(&args, keyword).map_err(err)?;
+                let value = (value.x + value.y + value.z) / 3.0;
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(err(format!("`{}` must be between 0 and 1", keyword)));
+                }
+                let pbr = entry.pbr.get_or_insert_with(PbrEntry::default);
+                let field = match keyword {
+                    "Pr" => &mut pbr.roughness,
+                    "Pm" => &mut pbr.metallic,
+                    "Ps" => &mut pbr.sheen,
+                    "Pc" => &mut pbr.clearcoat,
+                    "Pcr" => &mut pbr.clearcoat_roughness,
+                    _ => &mut pbr.anisotropy,
+                };
+                *field = Some(value);
+            }
+            // ambient/emissive colors and the other texture maps have no
+            // equivalent yet
+            _ => {}
+        }
+    }
+    if let Some(entry) = current.take() {
+        finish(entry, &mut materials);
+    }
+    Ok(materials)
+}
+
+/// Position, UV and normal index of one face corner, all 0-based.
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+struct MeshBuilder {
+    group: String,
+    material_name: Option<String>,
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    indices: Vec<[usize; 3]>,
+    has_normals: bool,
+    has_uvs: bool,
+    lookup: HashMap<FaceVertex, usize>,
+}
+impl MeshBuilder {
+    fn new(group: String, material_name: Option<String>) -> Self {
+        Self {
+            group,
+            material_name,
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: Vec::new(),
+            has_normals: true,
+            has_uvs: true,
+            lookup: HashMap::new(),
+        }
+    }
+
+    fn push_polygon(
+        &mut self,
+        corners: &[FaceVertex],
+        positions: &[Vec3],
+        uvs: &[(f64, f64)],
+        normals: &[Vec3],
+    ) {
+        let local: Vec<usize> = corners
+            .iter()
+            .map(|corner| {
+                if let Some(&index) = self.lookup.get(corner) {
+                    return index;
+                }
+                let (p, t, n) = *corner;
+                self.positions.push(positions[p]);
+                self.uvs.push(t.map_or((0.0, 0.0), |t| uvs[t]));
+                self.normals.push(n.map_or(Vec3::default(), |n| normals[n]));
+                self.has_uvs &= t.is_some();
+                self.has_normals &= n.is_some();
+                let index = self.positions.len() - 1;
+                self.lookup.insert(*corner, index);
+                index
+            })
+            .collect();
+        for i in 1..local.len() - 1 {
+            self.indices.push([local[0], local[i], local[i + 1]]);
+        }
+    }
+
+    fn build(self, material: Material) -> ObjMesh {
+        let normals = if self.has_normals {
+            self.normals
+        } else {
+            Vec::new()
+        };
+        let uvs = if self.has_uvs { self.uvs } else { Vec::new() };
+        ObjMesh {
+            group: self.group,
+            material_name: self.material_name,
+            // indices only ever point at vertices the builder added
+            mesh: TriangleMesh::new(self.positions, normals, uvs, self.indices, material)
+                .expect("mesh builder made an invalid mesh"),
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Yields `(line number, text)` with comments stripped and `\` continuations
+/// joined. The line number is that of the first physical line.
+fn logical_lines(source: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (i, raw) in source.lines().enumerate() {
+        let text = raw.split('#').next().unwrap_or("");
+        let (start, mut joined) = pending.take().unwrap_or((i + 1, String::new()));
+        if let Some(stripped) = text.trim_end().strip_suffix('\\') {
+            joined.push_str(stripped);
+            joined.push(' ');
+            pending = Some((start, joined));
+        } else {
+            joined.push_str(text);
+            lines.push((start, joined));
+        }
+    }
+    if let Some(line) = pending {
+        lines.push(line);
+    }
+    lines
+}
+
+fn parse_f64(token: &str) -> Result<f64, String> {
+    token
+        .parse::<f64>()
+        .map_err(|_| format!("expected a number, got `{}`", token))
+}
+
+fn parse_vec3(args: &[&str], keyword: &str) -> Result<Vec3, String> {
+    if args.len() < 3 {
+        return Err(format!(
+            "`{}` needs 3 coordinates, got {}",
+            keyword,
+            args.len()
+        ));
+    }
+    Ok(Vec3::new(
+        parse_f64(args[0])?,
+        parse_f64(args[1])?,
+        parse_f64(args[2])?,
+    ))
+}
+
+fn parse_color(args: &[&str], keyword: &str) -> Result<Vec3, String> {
+    match args.len() {
+        // a single value means grey
+        1 => {
+            let v = parse_f64(args[0])?;
+            Ok(Vec3::new(v, v, v))
+        }
+        _ if args[0] == "spectral" || args[0] == "xyz" => Err(format!(
+            "`{} {}` colors are not supported",
+            keyword, args[0]
+        )),
+        _ => parse_vec3(args, keyword),
+    }
+}
+
+fn parse_scalar(args: &[&str], keyword: &str) -> Result<f64, String> {
+    match args.first() {
+        Some(token) => parse_f64(token),
+        None => Err(format!("`{}` needs a value", keyword)),
+    }
+}
+
+/// Resolves a 1-based (or negative, relative to the end) OBJ index.
+fn resolve_index(token: &str, count: usize, what: &str) -> Result<usize, String> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| format!("invalid {} index `{}`", what, token))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else if index < 0 {
+        count as i64 + index
+    } else {
+        return Err(format!("{} index 0 is not allowed", what));
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!(
+            "{} index {} out of range ({} defined)",
+            what, index, count
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_face_vertex(
+    token: &str,
+    position_count: usize,
+    uv_count: usize,
+    normal_count: usize,
+) -> Result<FaceVertex, String> {
+    let mut parts = token.split('/');
+    let position = resolve_index(parts.next().unwrap_or(""), position_count, "vertex")?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        Some(t) => Some(resolve_index(t, uv_count, "texture coordinate")?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(n) => Some(resolve_index(n, normal_count, "normal")?),
+    };
+    if parts.next().is_some() {
+        return Err(format!("malformed face vertex `{}`", token));
+    }
+    Ok((position, uv, normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<ObjModel, ObjError> {
+        ObjModel::parse(source, Path::new("test.obj"), Path::new(""), None)
+    }
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        let relative = parse(&format!("{SQUARE}f -4 -3 -2\n")).unwrap();
+        let absolute = parse(&format!("{SQUARE}f 1 2 3\n")).unwrap();
+        let (relative, absolute) = (&relative.meshes[0].mesh.data, &absolute.meshes[0].mesh.data);
+        assert_eq!(relative.indices, absolute.indices);
+        for (a, b) in relative.positions.iter().zip(&absolute.positions) {
+            assert_eq!((a.x, a.y, a.z), (b.x, b.y, b.z));
+        }
+    }
+
+    #[test]
+    fn polygons_are_fan_triangulated() {
+        let model = parse("v 0 0 0\nv 1 0 0\nv 2 1 0\nv 1 2 0\nv 0 1 0\nf 1 2 3 4 5\n").unwrap();
+        assert_eq!(
+            model.meshes[0].mesh.data.indices,
+            vec![[0, 1, 2], [0, 2, 3], [0, 3, 4]]
+        );
+    }
+
+    #[test]
+    fn normals_are_dropped_unless_every_face_has_them() {
+        let smooth = parse(&format!(
+            "{SQUARE}vn 0 0 1\nf 1//1 2//1 3//1\nf 1//1 3//1 4//1\n"
+        ));
+        assert_eq!(smooth.unwrap().meshes[0].mesh.data.normals.len(), 4);
+        let mixed = parse(&format!("{SQUARE}vn 0 0 1\nf 1//1 2//1 3//1\nf 1 3 4\n"));
+        assert!(mixed.unwrap().meshes[0].mesh.data.normals.is_empty());
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let message = |source: &str| parse(source).err().unwrap().to_string();
+        assert_eq!(
+            message(&format!("{SQUARE}\nf 1 2 5\n")),
+            "test.obj:6: vertex index 5 out of range (4 defined)"
+        );
+        assert_eq!(
+            message(&format!("{SQUARE}f 1 2 0\n")),
+            "test.obj:5: vertex index 0 is not allowed"
+        );
+        assert_eq!(
+            message("v 0 0\n"),
+            "test.obj:1: `v` needs 3 coordinates, got 2"
+        );
+        assert_eq!(
+            message(&format!("{SQUARE}usemtl missing\n")),
+            "test.obj:5: unknown material `missing`"
+        );
+    }
+
+    #[test]
+    fn mtl_index_of_refraction_must_be_positive() {
+        let error = parse_mtl("newmtl glass\nillum 7\nNi 0\n", Path::new("test.mtl"))
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "test.mtl:3: `Ni` must be positive, got `0`"
+        );
+        assert!(parse_mtl("newmtl glass\nNi 1.5\n", Path::new("test.mtl")).is_ok());
+    }
+
+    #[test]
+    fn material_override_needs_no_mtl() {
+        let source = format!("mtllib missing.mtl\n{SQUARE}usemtl paint\nf 1 2 3\n");
+        assert!(matches!(parse(&source), Err(ObjError::Io { .. })));
+        let model = ObjModel::parse(
+            &source,
+            Path::new("test.obj"),
+            Path::new(""),
+            Some(DEFAULT_MATERIAL),
+        )
+        .unwrap();
+        assert_eq!(model.meshes[0].material_name.as_deref(), Some("paint"));
+    }
+}
@@ -0,0 +1,414 @@
+use crate::{
+    framebuffer::{Framebuffer, Image},
+    vec3::Vec3,
+};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// File formats the renderer can save to, picked from the file extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Binary (P6) PPM, 8-bit sRGB.
+    Ppm,
+    /// 8-bit sRGB PNG.
+    Png,
+    /// Portable float map holding the linear radiance.
+    Pfm,
+    /// Radiance RGBE, run-length encoded.
+    Hdr,
+    /// OpenEXR scanline image, see `ExrOptions`.
+    Exr,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            "pfm" => Some(ImageFormat::Pfm),
+            "hdr" => Some(ImageFormat::Hdr),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExrPrecision {
+    #[default]
+    Half,
+    Float,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExrCompression {
+    None,
+    #[default]
+    Zip,
+    Piz,
+}
+
+/// Settings for the formats that have any. The defaults are half floats with
+/// ZIP compression.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OutputOptions {
+    pub exr_precision: ExrPrecision,
+    pub exr_compression: ExrCompression,
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    UnsupportedFormat { path: PathBuf },
+    Io { path: PathBuf, source: io::Error },
+    Encode { path: PathBuf, message: String },
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::UnsupportedFormat { path } => write!(
+                f,
+                "{}: unsupported output format (expected .ppm, .png, .pfm, .hdr or .exr)",
+                path.display()
+            ),
+            OutputError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            OutputError::Encode { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for OutputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OutputError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Saves an image to `path`, in the format its extension names.
+pub fn write_image(path: &Path, image: &Image) -> Result<(), OutputError> {
+    write_image_with(path, image, &OutputOptions::default())
+}
+
+pub fn write_image_with(
+    path: &Path,
+    image: &Image,
+    options: &OutputOptions,
+) -> Result<(), OutputError> {
+    let format = ImageFormat::from_path(path).ok_or_else(|| OutputError::UnsupportedFormat {
+        path: path.to_path_buf(),
+    })?;
+    let io_error = |source| OutputError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    match format {
+        ImageFormat::Ppm => write_ppm(path, image).map_err(io_error),
+        ImageFormat::Pfm => write_pfm(path, image).map_err(io_error),
+        ImageFormat::Hdr => write_hdr(path, image).map_err(io_error),
+        ImageFormat::Png => write_png(path, image),
+        ImageFormat::Exr => write_exr(path, image, options),
+    }
+}
+
+fn srgb_bytes(image: &Image) -> Vec<u8> {
+    image.pixels.iter().flat_map(encode_srgb8).collect()
+}
+
+fn write_ppm(path: &Path, image: &Image) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P6\n{} {}\n255\n", image.width, image.height)?;
+    file.write_all(&srgb_bytes(image))?;
+    file.flush()
+}
+
+fn write_png(path: &Path, image: &Image) -> Result<(), OutputError> {
+    let image =
+        image::RgbImage::from_raw(image.width as u32, image.height as u32, srgb_bytes(image))
+            .ok_or_else(|| OutputError::Encode {
+                path: path.to_path_buf(),
+                message: "image size doesn't match its pixels".to_string(),
+            })?;
+    image
+        .save_with_format(path, image::ImageFormat::Png)
+        .map_err(|err| match err {
+            image::ImageError::IoError(source) => OutputError::Io {
+                path: path.to_path_buf(),
+                source,
+            },
+            other => OutputError::Encode {
+                path: path.to_path_buf(),
+                message: other.to_string(),
+            },
+        })
+}
+
+/// PFM stores rows bottom to top; a negative scale marks little-endian data.
+fn write_pfm(path: &Path, image: &Image) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "PF\n{} {}\n-1.0\n", image.width, image.height)?;
+    for row in image.rows().rev() {
+        for pixel in row {
+            for channel in [pixel.x, pixel.y, pixel.z] {
+                file.write_all(&(channel as f32).to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
+
+fn write_hdr(path: &Path, image: &Image) -> io::Result<()> {
+    let (width, height) = (image.width, image.height);
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(
+        file,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        height, width
+    )?;
+    for row in image.rows() {
+        let pixels: Vec<[u8; 4]> = row.iter().map(encode_rgbe).collect();
+        // the run-length scheme can only describe these widths
+        if !(8..0x8000).contains(&width) {
+            for pixel in &pixels {
+                file.write_all(pixel)?;
+            }
+            continue;
+        }
+        file.write_all(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8])?;
+        for channel in 0..4 {
+            let bytes: Vec<u8> = pixels.iter().map(|pixel| pixel[channel]).collect();
+            write_rle(&mut file, &bytes)?;
+        }
+    }
+    file.flush()
+}
+
+/// Shared-exponent encoding: three 8-bit mantissas and a biased exponent.
+fn encode_rgbe(color: &Vec3) -> [u8; 4] {
+    let channel = |value: f64| if value.is_nan() { 0.0 } else { value.max(0.0) };
+    let (r, g, b) = (channel(color.x), channel(color.y), channel(color.z));
+    let max = r.max(g).max(b);
+    if max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f64.powi(exponent);
+    let mantissa = |value: f64| (value * scale).min(255.0) as u8;
+    [
+        mantissa(r),
+        mantissa(g),
+        mantissa(b),
+        (exponent + 128).clamp(0, 255) as u8,
+    ]
+}
+
+/// Writes one channel of a scanline as runs of a repeated byte (count above
+/// 128) and literal stretches (count up to 128).
+fn write_rle(file: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    const MIN_RUN: usize = 4;
+    let mut current = 0;
+    while current < bytes.len() {
+        // find the next run long enough to be worth encoding
+        let mut run_start = current;
+        let mut run_length = 0;
+        let mut previous_length = 0;
+        while run_length < MIN_RUN && run_start < bytes.len() {
+            run_start += run_length;
+            previous_length = run_length;
+            run_length = 1;
+            while run_start + run_length < bytes.len()
+                && run_length < 127
+                && bytes[run_start] == bytes[run_start + run_length]
+            {
+                run_length += 1;
+            }
+        }
+        // a short run right before it is still cheaper as a run
+        if previous_length > 1 && previous_length == run_start - current {
+            file.write_all(&[128 + previous_length as u8, bytes[current]])?;
+            current = run_start;
+        }
+        while current < run_start {
+            let literal = (run_start - current).min(128);
+            file.write_all(&[literal as u8])?;
+            file.write_all(&bytes[current..current + literal])?;
+            current += literal;
+        }
+        if run_length >= MIN_RUN {
+            file.write_all(&[128 + run_length as u8, bytes[run_start]])?;
+            current += run_length;
+        }
+    }
+    Ok(())
+}
+
+fn write_exr(path: &Path, image: &Image, options: &OutputOptions) -> Result<(), OutputError> {
+    use exr::prelude::*;
+
+    let encoding = Encoding {
+        compression: match options.exr_compression {
+            ExrCompression::None => Compression::Uncompressed,
+            ExrCompression::Zip => Compression::ZIP16,
+            ExrCompression::Piz => Compression::PIZ,
+        },
+        blocks: Blocks::ScanLines,
+        line_order: LineOrder::Increasing,
+    };
+    let size = (image.width, image.height);
+    let result = match options.exr_precision {
+        ExrPrecision::Half => {
+            let channels = SpecificChannels::rgb(|Vec2(x, y): Vec2<usize>| {
+                let pixel = image.get(x, y);
+                (
+                    f16::from_f64(pixel.x),
+                    f16::from_f64(pixel.y),
+                    f16::from_f64(pixel.z),
+                )
+            });
+            Image::from_layer(Layer::new(
+                size,
+                LayerAttributes::default(),
+                encoding,
+                channels,
+            ))
+            .write()
+            .to_file(path)
+        }
+        ExrPrecision::Float => {
+            let channels = SpecificChannels::rgb(|Vec2(x, y): Vec2<usize>| {
+                let pixel = image.get(x, y);
+                (pixel.x as f32, pixel.y as f32, pixel.z as f32)
+            });
+            Image::from_layer(Layer::new(
+                size,
+                LayerAttributes::default(),
+                encoding,
+                channels,
+            ))
+            .write()
+            .to_file(path)
+        }
+    };
+    result.map_err(|err| match err {
+        exr::error::Error::Io(source) => OutputError::Io {
+            path: path.to_path_buf(),
+            source,
+        },
+        other => OutputError::Encode {
+            path: path.to_path_buf(),
+            message: other.to_string(),
+        },
+    })
+}
+
+/// False-color image of per-pixel sample counts, from dark blue for the
+/// fewest samples through green and yellow to red for `max_samples`.
+pub fn sample_heatmap(framebuffer: &Framebuffer, max_samples: usize) -> Image {
+    const STOPS: [(f64, f64, f64); 5] = [
+        (0.0, 0.0, 0.2),
+        (0.0, 0.3, 1.0),
+        (0.0, 0.8, 0.1),
+        (1.0, 0.9, 0.0),
+        (1.0, 0.0, 0.0),
+    ];
+    let color = |count: usize| {
+        let t = (count as f64 / max_samples.max(1) as f64).clamp(0.0, 1.0);
+        let position = t * (STOPS.len() - 1) as f64;
+        let index = (position as usize).min(STOPS.len() - 2);
+        let f = position - index as f64;
+        let (a, b) = (STOPS[index], STOPS[index + 1]);
+        Vec3::new(
+            a.0 + (b.0 - a.0) * f,
+            a.1 + (b.1 - a.1) * f,
+            a.2 + (b.2 - a.2) * f,
+        )
+    };
+    Image {
+        width: framebuffer.width,
+        height: framebuffer.height,
+        pixels: framebuffer
+            .pixels
+            .iter()
+            .map(|pixel| color(pixel.count))
+            .collect(),
+    }
+}
+
+/// The sRGB transfer curve, for a linear value in `[0, 1]`.
+pub fn linear_to_srgb(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Clamps a linear color to `[0, 1]` and encodes it as 8-bit sRGB. NaN
+/// channels come out black.
+pub fn encode_srgb8(color: &Vec3) -> [u8; 3] {
+    let encode = |linear: f64| {
+        let linear = if linear.is_nan() {
+            0.0
+        } else {
+            linear.clamp(0.0, 1.0)
+        };
+        (linear_to_srgb(linear) * 255.0).round() as u8
+    };
+    [encode(color.x), encode(color.y), encode(color.z)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hdr_round_trips_through_a_decoder() {
+        // each row starts with a run longer than the 127 one RLE packet
+        // holds, then changes every pixel
+        let (width, height) = (200, 3);
+        let pixels = (0..width * height)
+            .map(|index| {
+                let (i, j) = ((index % width) as f64, (index / width) as f64);
+                if i < 150.0 {
+                    Vec3::new(1.5 + j, 0.25, 3.0)
+                } else {
+                    Vec3::new(i * 0.01, 0.1 + j * i * 0.003, 40.0 / i)
+                }
+            })
+            .collect();
+        let image = Image {
+            width,
+            height,
+            pixels,
+        };
+        let path = std::env::temp_dir().join(format!("raytracer-{}.hdr", std::process::id()));
+        write_hdr(&path, &image).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            bytes.len() < width * height * 4 / 2,
+            "scanlines weren't compressed"
+        );
+
+        let decoded = image::load_from_memory_with_format(&bytes, image::ImageFormat::Hdr)
+            .unwrap()
+            .into_rgb32f();
+        assert_eq!((decoded.width(), decoded.height()), (200, 3));
+        for (expected, actual) in image.pixels.iter().zip(decoded.pixels()) {
+            // 8 bits of mantissa relative to the brightest channel
+            let tolerance = expected.x.max(expected.y).max(expected.z) / 128.0;
+            for (expected, actual) in [expected.x, expected.y, expected.z].iter().zip(actual.0) {
+                assert!(
+                    (expected - actual as f64).abs() <= tolerance,
+                    "{expected} came back as {actual}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use raytracer_rust::{
+    camera::Camera,
+    hit_record::{HitRecord, Hittable},
+    interval::Interval,
+    light::LightList,
+    output, RenderSettings, Scene, Vec3,
+};
+use sdl2::{
+    event::Event,
+    keyboard::{Keycode, Scancode},
+    mouse::MouseButton,
+    pixels::PixelFormatEnum,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const CONTROLS: &str = "\
+controls:
+  W/A/S/D, Q/E      move forward/left/back/right, down/up (hold Shift for speed)
+  right mouse drag  look around
+  scroll            zoom (field of view)
+  [ / ]             narrower/wider aperture (defocus angle)
+  - / =             nearer/farther focus distance
+  left click        focus on the clicked surface
+  Escape            quit";
+
+/// Degrees of rotation per pixel of mouse movement.
+const LOOK_SENSITIVITY: f64 = 0.2;
+
+/// Sum of the passes finished with the current camera.
+struct Accumulator {
+    camera: Arc<Camera>,
+    /// Bumped on every camera change so passes started with an older camera
+    /// are thrown away.
+    generation: u64,
+    /// Row by row from the top.
+    sum: Vec<Vec3>,
+    passes: usize,
+    started: Instant,
+    elapsed: Duration,
+}
+
+impl Accumulator {
+    fn restart(&mut self, camera: Camera) {
+        self.camera = Arc::new(camera);
+        self.generation += 1;
+        self.sum.fill(Vec3::default());
+        self.passes = 0;
+        self.started = Instant::now();
+        self.elapsed = Duration::ZERO;
+    }
+}
+
+/// Position and orientation of the preview camera, as yaw and pitch in
+/// degrees so mouse-look never rolls the view.
+struct FlyCamera {
+    position: Vec3,
+    yaw: f64,
+    pitch: f64,
+    fov: f64,
+    defocus_angle: f64,
+    focus_dist: f64,
+    /// World units per second.
+    speed: f64,
+}
+
+impl FlyCamera {
+    fn new(camera: &Camera) -> Self {
+        let offset = Vec3::sub(&camera.look_at(), &camera.center);
+        let forward = Vec3::unit(&offset);
+        Self {
+            position: camera.center,
+            yaw: forward.z.atan2(forward.x).to_degrees(),
+            pitch: forward.y.clamp(-1.0, 1.0).asin().to_degrees(),
+            fov: camera.fov(),
+            defocus_angle: camera.defocus_angle(),
+            focus_dist: camera.focus_dist(),
+            speed: offset.length().max(1.0) * 0.5,
+        }
+    }
+
+    fn forward(&self) -> Vec3 {
+        let (yaw, pitch) = (self.yaw.to_radians(), self.pitch.to_radians());
+        Vec3::new(
+            pitch.cos() * yaw.cos(),
+            pitch.sin(),
+            pitch.cos() * yaw.sin(),
+        )
+    }
+
+    fn right(&self) -> Vec3 {
+        Vec3::unit(&Vec3::cross(&self.forward(), &Vec3::new(0.0, 1.0, 0.0)))
+    }
+
+    fn look(&mut self, dx: f64, dy: f64) {
+        self.yaw += dx * LOOK_SENSITIVITY;
+        self.pitch = (self.pitch - dy * LOOK_SENSITIVITY).clamp(-89.0, 89.0);
+    }
+
+    fn camera(&self, base: &Camera) -> Camera {
+        base.with_view(
+            self.position,
+            Vec3::add(&self.position, &self.forward()),
+            self.fov,
+            self.defocus_angle,
+            self.focus_dist,
+        )
+    }
+}
+
+/// Renders in the background one sample per pixel at a time and shows the
+/// running average in a window, until `samples_per_pixel` passes are done.
+/// Adaptive sampling and tiles don't apply here.
+/// The camera can be moved while rendering; every change starts the
+/// accumulation over.
+pub fn run(scene: Scene, settings: RenderSettings) -> Result<(), String> {
+    let Scene {
+        camera,
+        world,
+        lights,
+        ..
+    } = scene;
+    let (width, height) = (camera.image_width, camera.image_height);
+    let target_passes = settings.samples_per_pixel;
+    let world: Arc<dyn Hittable> = Arc::from(world);
+    let mut fly = FlyCamera::new(&camera);
+
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let window = video_subsystem
+        .window("raytracer", width as u32, height as u32)
+        .position_centered()
+        .build()
+        .map_err(|err| err.to_string())?;
+    let mut canvas = window
+        .into_canvas()
+        .build()
+        .map_err(|err| err.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+        .map_err(|err| err.to_string())?;
+    let mut event_pump = sdl_context.event_pump()?;
+    eprintln!("{}", CONTROLS);
+
+    let accumulator = Arc::new(Mutex::new(Accumulator {
+        camera: Arc::new(camera),
+        generation: 0,
+        sum: vec![Vec3::default(); width * height],
+        passes: 0,
+        started: Instant::now(),
+        elapsed: Duration::ZERO,
+    }));
+    let cancelled = Arc::new(AtomicBool::new(false));
+    {
+        let accumulator = accumulator.clone();
+        let cancelled = cancelled.clone();
+        let world = world.clone();
+        thread::spawn(move || {
+            render_passes(&accumulator, &cancelled, world.as_ref(), &lights, &settings)
+        });
+    }
+
+    let mut pixels = vec![0u8; width * height * 3];
+    let mut shown_passes = 0;
+    let mut last_frame = Instant::now();
+    'running: loop {
+        let frame_time = last_frame.elapsed().as_secs_f64();
+        last_frame = Instant::now();
+        let mut changed = false;
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                Event::MouseMotion {
+                    mousestate,
+                    xrel,
+                    yrel,
+                    ..
+                } if mousestate.right() => {
+                    fly.look(xrel as f64, yrel as f64);
+                    changed = true;
+                }
+                Event::MouseWheel { y, .. } => {
+                    fly.fov = (fly.fov - 2.0 * y as f64).clamp(1.0, 170.0);
+                    changed = true;
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    let camera = accumulator
+                        .lock()
+                        .map_err(|_| "render thread panicked")?
+                        .camera
+                        .clone();
+                    let (i, j) = (x.max(0) as usize, y.max(0) as usize);
+                    if let Some(distance) = focus_distance(&camera, world.as_ref(), i, j) {
+                        fly.focus_dist = distance;
+                        changed = true;
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    changed |= match keycode {
+                        Keycode::LeftBracket => {
+                            fly.defocus_angle = (fly.defocus_angle - 0.5).max(0.0);
+                            true
+                        }
+                        Keycode::RightBracket => {
+                            fly.defocus_angle += 0.5;
+                            true
+                        }
+                        Keycode::Minus => {
+                            fly.focus_dist = (fly.focus_dist / 1.1).max(0.01);
+                            true
+                        }
+                        Keycode::Equals => {
+                            fly.focus_dist *= 1.1;
+                            true
+                        }
+                        _ => false,
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        let keyboard = event_pump.keyboard_state();
+        let boost = if keyboard.is_scancode_pressed(Scancode::LShift) {
+            4.0
+        } else {
+            1.0
+        };
+        let step = fly.speed * boost * frame_time;
+        let (forward, right) = (fly.forward(), fly.right());
+        let moves = [
+            (Scancode::W, forward),
+            (Scancode::S, Vec3::mul(&forward, -1.0)),
+            (Scancode::D, right),
+            (Scancode::A, Vec3::mul(&right, -1.0)),
+            (Scancode::E, Vec3::new(0.0, 1.0, 0.0)),
+            (Scancode::Q, Vec3::new(0.0, -1.0, 0.0)),
+        ];
+        for (scancode, direction) in moves {
+            if keyboard.is_scancode_pressed(scancode) {
+                fly.position = Vec3::add(&fly.position, &Vec3::mul(&direction, step));
+                changed = true;
+            }
+        }
+
+        // only copy out of the accumulator when a new pass has landed
+        let update = {
+            let mut accumulator = accumulator.lock().map_err(|_| "render thread panicked")?;
+            if changed {
+                let camera = fly.camera(&accumulator.camera);
+                accumulator.restart(camera);
+                shown_passes = 0;
+            }
+            if accumulator.passes > shown_passes {
+                let scale = 1.0 / accumulator.passes as f64;
+                for (pixel, sum) in pixels.chunks_exact_mut(3).zip(&accumulator.sum) {
+                    pixel.copy_from_slice(&output::encode_srgb8(&Vec3::mul(sum, scale)));
+                }
+                Some((accumulator.passes, accumulator.elapsed))
+            } else {
+                None
+            }
+        };
+        if let Some((passes, elapsed)) = update {
+            shown_passes = passes;
+            texture
+                .update(None, &pixels, width * 3)
+                .map_err(|err| err.to_string())?;
+            let status = if passes == target_passes {
+                ", done"
+            } else {
+                ""
+            };
+            let title = format!(
+                "raytracer - {}/{} spp, {:.1}s{} | fov {:.0}, focus {:.2}, aperture {:.1}",
+                passes,
+                target_passes,
+                elapsed.as_secs_f64(),
+                status,
+                fly.fov,
+                fly.focus_dist,
+                fly.defocus_angle
+            );
+            canvas
+                .window_mut()
+                .set_title(&title)
+                .map_err(|err| err.to_string())?;
+        }
+
+        canvas.clear();
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+    }
+    cancelled.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Worker loop: renders passes with the accumulator's current camera and adds
+/// them in, unless the camera changed in the meantime.
+fn render_passes(
+    accumulator: &Mutex<Accumulator>,
+    cancelled: &AtomicBool,
+    world: &dyn Hittable,
+    lights: &LightList,
+    settings: &RenderSettings,
+) {
+    while !cancelled.load(Ordering::Relaxed) {
+        let (camera, generation, pass) = {
+            let Ok(accumulator) = accumulator.lock() else {
+                return;
+            };
+            if accumulator.passes >= settings.samples_per_pixel {
+                drop(accumulator);
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+            (
+                accumulator.camera.clone(),
+                accumulator.generation,
+                accumulator.passes,
+            )
+        };
+
+        let pass = camera.render_pass(world, lights, settings, pass);
+
+        let Ok(mut accumulator) = accumulator.lock() else {
+            return;
+        };
+        if accumulator.generation != generation {
+            continue;
+        }
+        for (sum, color) in accumulator.sum.iter_mut().zip(pass) {
+            *sum = Vec3::add(sum, &color);
+        }
+        accumulator.passes += 1;
+        accumulator.elapsed = accumulator.started.elapsed();
+    }
+}
+
+/// Distance from the camera to the surface seen through pixel `(i, j)`,
+/// measured along the view direction like the focus distance is.
+fn focus_distance(camera: &Camera, world: &dyn Hittable, i: usize, j: usize) -> Option<f64> {
+    if i >= camera.image_width || j >= camera.image_height {
+        return None;
+    }
+    let ray = camera.pixel_ray(i, j);
+    let mut hit_record = HitRecord::new();
+    if !world.hit(
+        &ray,
+        &Interval::new_with_values(0.001, f64::INFINITY),
+        &mut hit_record,
+    ) {
+        return None;
+    }
+    let view_direction = Vec3::unit(&Vec3::sub(&camera.look_at(), &camera.center));
+    let distance = Vec3::dot(
+        &Vec3::sub(&hit_record.point, &camera.center),
+        &view_direction,
+    );
+    (distance > 0.0).then_some(distance)
+}
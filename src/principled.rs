@@ -0,0 +1,381 @@
+use crate::{
+    hit_record::HitRecord,
+    microfacet::{self, Ggx},
+    sampler::{self, Sampler},
+    texture::Texture,
+    vec3::Vec3,
+};
+use std::f64::consts::PI;
+
+// A layered material in the spirit of the Disney and OpenPBR models. From
+// the top: an optional clear coat, then a GGX specular layer over either a
+// metal, or a dielectric whose light that isn't reflected is diffused by the
+// base or, with transmission, refracted through it. Each layer only gets
+// the light the ones above let through, so no combination of parameters
+// reflects more than comes in.
+
+/// Parameters of the principled material, all textures. The scalar ones use
+/// the average of the texture's channels and, apart from `ior`, go from 0
+/// to 1.
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: Texture,
+    /// Blends from a dielectric to a metal whose reflectance is the base
+    /// color.
+    pub metallic: Texture,
+    pub roughness: Texture,
+    /// Scales the reflectance of the dielectric; 0.5 is what `ior` gives,
+    /// 0 removes reflections and 1 doubles them.
+    pub specular: Texture,
+    /// Tints the dielectric reflections towards the base color.
+    pub specular_tint: Texture,
+    /// A soft white rim at grazing angles, like on cloth.
+    pub sheen: Texture,
+    pub clearcoat: Texture,
+    pub clearcoat_roughness: Texture,
+    /// The part of the dielectric that refracts instead of diffusing, tinted
+    /// by the base color.
+    pub transmission: Texture,
+    /// Index of refraction, taken as 1 where it isn't positive.
+    pub ior: Texture,
+    /// Stretches the highlights along the first tangent of the shading
+    /// frame.
+    pub anisotropy: Texture,
+}
+
+impl Default for Principled {
+    /// A rough, gray plastic.
+    fn default() -> Self {
+        let constant = |value| Texture::Solid(Vec3::new(value, value, value));
+        Self {
+            base_color: constant(0.8),
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            clearcoat: constant(0.0),
+            clearcoat_roughness: constant(0.1),
+            transmission: constant(0.0),
+            ior: constant(1.5),
+            anisotropy: constant(0.0),
+        }
+    }
+}
+
+/// Roughness is kept above this so the lobes can always be evaluated; a
+/// perfect mirror needs `Material::Conductor` or `Material::Dielectric`.
+const MIN_ROUGHNESS: f64 = 0.05;
+
+/// Index of refraction of the clear coat.
+const CLEARCOAT_IOR: f64 = 1.5;
+
+impl Principled {
+    /// The parameters looked up at a hit point.
+    pub fn at(&self, hit_record: &HitRecord) -> PrincipledLobes {
+        let average = |texture: &Texture| {
+            let value = texture.value(hit_record.u, hit_record.v, &hit_record.point);
+            (value.x + value.y + value.z) / 3.0
+        };
+        let scalar = |texture: &Texture| average(texture).clamp(0.0, 1.0);
+        let ior = match average(&self.ior) {
+            ior if ior > 0.0 => ior,
+            _ => 1.0,
+        };
+        let base_color = self
+            .base_color
+            .value(hit_record.u, hit_record.v, &hit_record.point);
+        if !hit_record.front_face {
+            // only light that was transmitted gets inside, and it leaves
+            // through the same interface
+            return PrincipledLobes {
+                base_color: Vec3::new(1.0, 1.0, 1.0),
+                metallic: 0.0,
+                // so total internal reflection stays total
+                specular: 0.5,
+                specular_color: Vec3::new(1.0, 1.0, 1.0),
+                sheen: 0.0,
+                clearcoat: 0.0,
+                transmission: 1.0,
+                eta: 1.0 / ior,
+                specular_lobe: Ggx::anisotropic(
+                    scalar(&self.roughness).max(MIN_ROUGHNESS),
+                    scalar(&self.anisotropy),
+                ),
+                clearcoat_lobe: Ggx::new(MIN_ROUGHNESS),
+            };
+        }
+
+        // normalized by the brightest channel rather than the luminance, so
+        // that tinting never makes a channel reflect more than untinted
+        let brightest = base_color.x.max(base_color.y).max(base_color.z);
+        let hue = if brightest > 0.0 {
+            Vec3::div(&base_color, brightest)
+        } else {
+            Vec3::new(1.0, 1.0, 1.0)
+        };
+        let tint = scalar(&self.specular_tint);
+        PrincipledLobes {
+            base_color,
+            metallic: scalar(&self.metallic),
+            specular: scalar(&self.specular),
+            specular_color: Vec3::add(
+                &Vec3::mul(&Vec3::new(1.0, 1.0, 1.0), 1.0 - tint),
+                &Vec3::mul(&hue, tint),
+            ),
+            sheen: scalar(&self.sheen),
+            clearcoat: scalar(&self.clearcoat),
+            transmission: scalar(&self.transmission),
+            eta: ior,
+            specular_lobe: Ggx::anisotropic(
+                scalar(&self.roughness).max(MIN_ROUGHNESS),
+                scalar(&self.anisotropy),
+            ),
+            clearcoat_lobe: Ggx::new(scalar(&self.clearcoat_roughness).max(MIN_ROUGHNESS)),
+        }
+    }
+}
+
+/// The principled material at one point, with all directions in the local
+/// shading frame and `wo` pointing away from the surface.
+pub struct PrincipledLobes {
+    base_color: Vec3,
+    metallic: f64,
+    specular: f64,
+    specular_color: Vec3,
+    sheen: f64,
+    clearcoat: f64,
+    transmission: f64,
+    /// Index of refraction of the other side over this one.
+    eta: f64,
+    specular_lobe: Ggx,
+    clearcoat_lobe: Ggx,
+}
+
+impl PrincipledLobes {
+    /// BSDF times cosine for light arriving from `wi`, and the density with
+    /// which `sample` picks `wi`.
+    pub fn eval(&self, wo: &Vec3, wi: &Vec3) -> (Vec3, f64) {
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return (Vec3::default(), 0.0);
+        }
+        let [coat_weight, specular_weight, transmission_weight, diffuse_weight] =
+            self.lobe_weights(wo);
+        let coat_o = self.clearcoat * microfacet::fresnel_dielectric(wo.z, CLEARCOAT_IOR);
+
+        if wi.z < 0.0 {
+            if transmission_weight == 0.0 {
+                return (Vec3::default(), 0.0);
+            }
+            let eta = self.eta;
+            let m = Vec3::mul(&Vec3::add(wo, &Vec3::mul(wi, eta)), -1.0);
+            if m.near_zero() {
+                return (Vec3::default(), 0.0);
+            }
+            let m = Vec3::unit(&m);
+            let m = if m.z < 0.0 { Vec3::mul(&m, -1.0) } else { m };
+            let (cos_o, cos_i) = (Vec3::dot(wo, &m), Vec3::dot(wi, &m));
+            if cos_o <= 0.0 || cos_i >= 0.0 {
+                return (Vec3::default(), 0.0);
+            }
+            let denominator = cos_o + eta * cos_i;
+            let jacobian = eta * eta * cos_i.abs() / (denominator * denominator);
+            let lobe = &self.specular_lobe;
+            let weight = (1.0 - coat_o)
+                * (1.0 - self.metallic)
+                * self.transmission
+                * (1.0 - self.dielectric_fresnel(cos_o))
+                * lobe.d(&m)
+                * lobe.g(wo, wi)
+                * cos_o
+                * jacobian
+                / wo.z;
+            let pdf = transmission_weight * lobe.visible_pdf(wo, &m) * jacobian;
+            return (Vec3::mul(&self.base_color, weight), pdf);
+        }
+
+        let m = Vec3::unit(&Vec3::add(wo, wi));
+        let cos_o = Vec3::dot(wo, &m);
+        if cos_o <= 0.0 {
+            return (Vec3::default(), 0.0);
+        }
+
+        let coat = &self.clearcoat_lobe;
+        let coat_value = self.clearcoat
+            * microfacet::fresnel_dielectric(cos_o, CLEARCOAT_IOR)
+            * coat.d(&m)
+            * coat.g(wo, wi)
+            / (4.0 * wo.z);
+        let coat_pdf = coat.visible_pdf(wo, &m) / (4.0 * cos_o);
+        let below_coat = (1.0 - coat_o)
+            * (1.0 - self.clearcoat * microfacet::fresnel_dielectric(wi.z, CLEARCOAT_IOR));
+
+        let lobe = &self.specular_lobe;
+        let metal = Vec3::mul(&schlick(&self.base_color, cos_o), self.metallic);
+        let dielectric = Vec3::mul(
+            &self.specular_color,
+            (1.0 - self.metallic) * self.dielectric_fresnel(cos_o),
+        );
+        let specular = Vec3::mul(
+            &Vec3::add(&metal, &dielectric),
+            lobe.d(&m) * lobe.g(wo, wi) / (4.0 * wo.z),
+        );
+        let specular_pdf = lobe.visible_pdf(wo, &m) / (4.0 * cos_o);
+
+        // what the specular layer lets through on the way in and out
+        let diffuse_scale = (1.0 - self.metallic)
+            * (1.0 - self.transmission)
+            * (1.0 - self.dielectric_fresnel(wo.z))
+            * (1.0 - self.dielectric_fresnel(wi.z));
+        let sheen = self.sheen * (1.0 - Vec3::dot(wi, &m)).clamp(0.0, 1.0).powi(5);
+        let diffuse = Vec3::mul(
+            &Vec3::add(
+                &Vec3::div(&self.base_color, PI),
+                &Vec3::new(sheen, sheen, sheen),
+            ),
+            diffuse_scale * wi.z,
+        );
+        let diffuse_pdf = wi.z / PI;
+
+        let value = Vec3::add(
+            &Vec3::new(coat_value, coat_value, coat_value),
+            &Vec3::mul(&Vec3::add(&specular, &diffuse), below_coat),
+        );
+        let pdf =
+            coat_weight * coat_pdf + specular_weight * specular_pdf + diffuse_weight * diffuse_pdf;
+        (value, pdf)
+    }
+
+    /// Picks a lobe and samples a direction from it, returning it with the
+    /// BSDF times cosine and the density of all lobes together, or `None`
+    /// if the sample is lost.
+    pub fn sample(&self, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<(Vec3, Vec3, f64)> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let weights = self.lobe_weights(wo);
+        let mut choice = sampler.next_1d();
+        let lobe = weights
+            .iter()
+            .position(|&weight| {
+                choice -= weight;
+                choice < 0.0
+            })
+            .unwrap_or(3);
+        let u = sampler.next_2d();
+        let wi = match lobe {
+            0 => microfacet::reflect(wo, &self.clearcoat_lobe.sample_visible_normal(wo, u)),
+            1 => microfacet::reflect(wo, &self.specular_lobe.sample_visible_normal(wo, u)),
+            2 => {
+                let m = self.specular_lobe.sample_visible_normal(wo, u);
+                microfacet::refract(wo, &m, self.eta)?
+            }
+            _ => sampler::sample_cosine_hemisphere(u),
+        };
+        // a lobe can send the ray to the wrong side of the surface, where
+        // `eval` would only count the other lobes
+        if (wi.z < 0.0) != (lobe == 2) {
+            return None;
+        }
+        let (value, pdf) = self.eval(wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((wi, value, pdf))
+    }
+
+    /// How often each of clear coat, specular, transmission and diffuse is
+    /// sampled, roughly in proportion to how much light it reflects.
+    fn lobe_weights(&self, wo: &Vec3) -> [f64; 4] {
+        let coat = self.clearcoat * microfacet::fresnel_dielectric(wo.z, CLEARCOAT_IOR);
+        let fresnel = self.dielectric_fresnel(wo.z);
+        let dielectric = (1.0 - coat) * (1.0 - self.metallic);
+        let weights = [
+            coat,
+            (1.0 - coat) * self.metallic + dielectric * fresnel,
+            dielectric * self.transmission * (1.0 - fresnel),
+            dielectric * (1.0 - self.transmission) * (1.0 - fresnel),
+        ];
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return [0.0, 1.0, 0.0, 0.0];
+        }
+        weights.map(|weight| weight / total)
+    }
+
+    fn dielectric_fresnel(&self, cos: f64) -> f64 {
+        (2.0 * self.specular * microfacet::fresnel_dielectric(cos, self.eta)).min(1.0)
+    }
+}
+
+/// Schlick's approximation of the Fresnel reflectance of a metal whose
+/// reflectance head-on is `f0`.
+fn schlick(f0: &Vec3, cos: f64) -> Vec3 {
+    let weight = (1.0 - cos).clamp(0.0, 1.0).powi(5);
+    Vec3::add(
+        f0,
+        &Vec3::mul(&Vec3::sub(&Vec3::new(1.0, 1.0, 1.0), f0), weight),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+
+    /// Fraction of light from `wo` the lobes send back out, by importance
+    /// sampling them.
+    fn albedo(lobes: &PrincipledLobes, wo: &Vec3) -> Vec3 {
+        const SAMPLES: usize = 20000;
+        let mut sampler = IndependentSampler::new(3);
+        let mut sum = Vec3::default();
+        for index in 0..SAMPLES {
+            sampler.start_sample(0, 0, index);
+            if let Some((_, value, pdf)) = lobes.sample(wo, &mut sampler) {
+                sum = Vec3::add(&sum, &Vec3::div(&value, pdf));
+            }
+        }
+        Vec3::div(&sum, SAMPLES as f64)
+    }
+
+    #[test]
+    fn reflects_no_more_light_than_comes_in() {
+        let constant = |value| Texture::Solid(Vec3::new(value, value, value));
+        let mut hit_record = HitRecord::new();
+        hit_record.front_face = true;
+        for base_color in [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ] {
+            for (metallic, transmission) in [(0.0, 0.0), (0.0, 1.0), (1.0, 0.0)] {
+                for roughness in [0.0, 0.3, 1.0] {
+                    let material = Principled {
+                        base_color: Texture::Solid(base_color),
+                        metallic: constant(metallic),
+                        roughness: constant(roughness),
+                        specular: constant(1.0),
+                        specular_tint: constant(1.0),
+                        sheen: constant(1.0),
+                        clearcoat: constant(1.0),
+                        clearcoat_roughness: constant(roughness),
+                        transmission: constant(transmission),
+                        ..Principled::default()
+                    };
+                    let lobes = material.at(&hit_record);
+                    for cos in [1.0_f64, 0.5, 0.1, 0.02] {
+                        let wo = Vec3::new((1.0 - cos * cos).sqrt(), 0.0, cos);
+                        let albedo = albedo(&lobes, &wo);
+                        // a little above 1 for the Monte Carlo error
+                        assert!(
+                            albedo.x <= 1.02 && albedo.y <= 1.02 && albedo.z <= 1.02,
+                            "{albedo:?} reflected for base color {base_color:?}, metallic \
+                             {metallic}, transmission {transmission}, roughness {roughness} \
+                             and cosine {cos}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
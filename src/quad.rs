@@ -0,0 +1,78 @@
+use crate::{
+    aabb::Aabb,
+    hit_record::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    vec3::Vec3,
+};
+
+/// Parallelogram with corner `q` and edges `u` and `v`.
+#[derive(Clone)]
+pub struct Quad {
+    pub q: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Material,
+    normal: Vec3,
+    d: f64,
+    w: Vec3,
+}
+impl Quad {
+    pub fn new(q: &Vec3, u: &Vec3, v: &Vec3, material: Material) -> Self {
+        let n = Vec3::cross(u, v);
+        let normal = Vec3::unit(&n);
+        Self {
+            q: *q,
+            u: *u,
+            v: *v,
+            material,
+            normal,
+            d: Vec3::dot(&normal, q),
+            w: Vec3::div(&n, Vec3::dot(&n, &n)),
+        }
+    }
+    pub fn normal(&self) -> Vec3 {
+        self.normal
+    }
+    pub fn area(&self) -> f64 {
+        Vec3::cross(&self.u, &self.v).length()
+    }
+}
+
+impl Hittable for Quad {
+    fn hit<'a>(&'a self, ray: &Ray, interval: &Interval, hit_record: &mut HitRecord<'a>) -> bool {
+        let denom = Vec3::dot(&self.normal, &ray.direction);
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+        let t = (self.d - Vec3::dot(&self.normal, &ray.origin)) / denom;
+        if !interval.surrounds(t) {
+            return false;
+        }
+
+        // planar coordinates of the hit point in terms of u and v
+        let point = ray.at(t);
+        let planar = Vec3::sub(&point, &self.q);
+        let alpha = Vec3::dot(&self.w, &Vec3::cross(&planar, &self.v));
+        let beta = Vec3::dot(&self.w, &Vec3::cross(&self.u, &planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return false;
+        }
+
+        hit_record.t = t;
+        hit_record.point = point;
+        hit_record.u = alpha;
+        hit_record.v = beta;
+        hit_record.set_face_normal(ray, &self.normal);
+        hit_record.material = Some(&self.material);
+        true
+    }
+    fn bounding_box(&self) -> Aabb {
+        let diagonal1 =
+            Aabb::new_from_points(&self.q, &Vec3::add(&self.q, &Vec3::add(&self.u, &self.v)));
+        let diagonal2 =
+            Aabb::new_from_points(&Vec3::add(&self.q, &self.u), &Vec3::add(&self.q, &self.v));
+        Aabb::union(&diagonal1, &diagonal2)
+    }
+}
@@ -1,13 +1,25 @@
-use crate::Vec3;
+use crate::{medium::Medium, spectrum::Wavelengths, vec3::Vec3};
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    /// The one wavelength in nanometers this path carries once dispersion
+    /// has split it, or `None` while it still carries all colors.
+    pub wavelength: Option<f64>,
+    /// The wavelengths the path carries when rendering spectrally, or
+    /// `None` when its colors are RGB.
+    pub wavelengths: Option<Wavelengths>,
+    /// The volume the ray travels through, if any. Fog is left out since
+    /// where it is doesn't depend on the path.
+    pub medium: Option<Medium>,
 }
 impl Ray {
     pub fn new(origin: &Vec3, direction: &Vec3) -> Ray {
         Ray {
             direction: *direction,
             origin: *origin,
+            wavelength: None,
+            wavelengths: None,
+            medium: None,
         }
     }
     pub fn at(&self, t: f64) -> Vec3 {
@@ -0,0 +1,111 @@
+use crate::{
+    framebuffer::{Framebuffer, Image, PixelStats},
+    sampler::SamplerKind,
+    scene::Scene,
+    tiles::{RenderHooks, TileScheduler},
+};
+
+/// How to render a scene, as opposed to what is in it.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    /// The most samples any pixel takes; every pixel takes this many unless
+    /// `adaptive` is set.
+    pub samples_per_pixel: usize,
+    /// Maximum number of bounces per path.
+    pub max_depth: usize,
+    /// Seeds every random decision of the render, so the same seed gives the
+    /// same image.
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub color_mode: ColorMode,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub tiles: TileScheduler,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            samples_per_pixel: 100,
+            max_depth: 50,
+            seed: 0,
+            sampler: SamplerKind::Independent,
+            color_mode: ColorMode::Rgb,
+            adaptive: None,
+            tiles: TileScheduler::default(),
+        }
+    }
+}
+
+/// What the colors of a path are.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorMode {
+    /// Red, green and blue.
+    #[default]
+    Rgb,
+    /// A few wavelengths per path, with RGB colors turned into smooth
+    /// spectra and lights given off as the spectrum of their illuminant.
+    /// Slower and noisier, but dispersion, colored light and blackbody
+    /// emitters mix the way they would in reality.
+    Spectral,
+}
+
+/// Lets pixels stop early once their estimate is good enough, so flat and
+/// evenly lit regions don't get as many samples as noisy ones.
+/// `samples_per_pixel` stays the upper bound.
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveSampling {
+    /// Samples every pixel gets before its noise is first checked.
+    pub min_samples: usize,
+    /// A pixel stops once the standard error of its luminance, measured after
+    /// a gamma 2 curve so dark pixels aren't held to a stricter standard
+    /// than the eye is, drops below this.
+    pub noise_threshold: f64,
+}
+
+impl AdaptiveSampling {
+    /// Samples taken between two checks of the noise, so a pixel isn't
+    /// stopped by a lucky streak of similar samples.
+    const BATCH: usize = 8;
+
+    /// Whether a pixel can stop. Only checked every `BATCH` samples.
+    pub fn converged(&self, stats: &PixelStats) -> bool {
+        stats.count >= self.min_samples
+            && (stats.count - self.min_samples).is_multiple_of(Self::BATCH)
+            && stats.error() < self.noise_threshold
+    }
+}
+
+/// Renders `scene` on the rayon thread pool and returns the linear radiance.
+pub fn render(scene: &Scene, settings: &RenderSettings) -> Image {
+    let camera = &scene.camera;
+    let framebuffer = Framebuffer::new(camera.image_width, camera.image_height);
+    render_with(scene, settings, framebuffer, &RenderHooks::default()).to_image()
+}
+
+/// Adds samples to `framebuffer` until it holds a full render of `scene`,
+/// with `hooks` to follow its progress, cancel it or save checkpoints.
+pub fn render_with(
+    scene: &Scene,
+    settings: &RenderSettings,
+    framebuffer: Framebuffer,
+    hooks: &RenderHooks,
+) -> Framebuffer {
+    scene.camera.render(
+        scene.world.as_ref(),
+        &scene.lights,
+        settings,
+        framebuffer,
+        hooks,
+    )
+}
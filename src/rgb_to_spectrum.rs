@@ -0,0 +1,262 @@
+//! The fit behind the smooth spectra `spectrum` gives RGB colors, after
+//! Jakob and Hanika (2019). It uses nothing else from the crate, since
+//! `build.rs` compiles it too and fits the table once per build rather than
+//! on every start.
+
+use std::sync::OnceLock;
+
+/// The visible range wavelengths are sampled from, in nanometers.
+pub const WAVELENGTH_MIN: f64 = 380.0;
+pub const WAVELENGTH_MAX: f64 = 780.0;
+
+/// Grid points along each axis of the table.
+pub const TABLE_SIZE: usize = 32;
+
+/// The CIE 1931 color matching functions at `wavelength`, using the
+/// multi-lobe Gaussian fit of Wyman, Sloan and Shirley (2013).
+pub fn cie_xyz(wavelength: f64) -> [f64; 3] {
+    let lobe = |mean: f64, below: f64, above: f64| {
+        let t = (wavelength - mean) / if wavelength < mean { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    [
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    ]
+}
+
+/// CIE XYZ to linear sRGB, for the D65 white point.
+pub fn xyz_to_linear_srgb(xyz: &[f64; 3]) -> [f64; 3] {
+    let [x, y, z] = *xyz;
+    [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ]
+}
+
+/// CIE standard illuminant D65 from 380 to 780 nm in steps of 10.
+const D65: [f64; 41] = [
+    49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008, 117.812, 114.861,
+    115.923, 108.811, 109.354, 107.802, 104.79, 107.689, 104.405, 104.046, 100.0, 96.3342, 95.788,
+    88.6856, 90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268, 80.2146, 82.2778, 78.2842,
+    69.7213, 71.6091, 74.349, 61.604, 69.8856, 75.087, 63.5927, 46.4182, 66.8054, 63.3828,
+];
+
+pub fn d65(wavelength: f64) -> f64 {
+    let position = ((wavelength - WAVELENGTH_MIN) / 10.0).clamp(0.0, 40.0);
+    let index = (position as usize).min(39);
+    let t = position - index as f64;
+    D65[index] * (1.0 - t) + D65[index + 1] * t
+}
+
+/// Wavelengths and weights for integrating over the visible range with the
+/// trapezoidal rule in 5 nm steps.
+pub fn quadrature() -> impl Iterator<Item = (f64, f64)> {
+    const STEPS: usize = 80;
+    let step = (WAVELENGTH_MAX - WAVELENGTH_MIN) / STEPS as f64;
+    (0..=STEPS).map(move |i| {
+        let weight = if i == 0 || i == STEPS {
+            0.5 * step
+        } else {
+            step
+        };
+        (WAVELENGTH_MIN + i as f64 * step, weight)
+    })
+}
+
+/// Linear sRGB of light at just `wavelength`, scaled so that the D65
+/// spectrum integrates to exactly white.
+pub fn rgb_matching(wavelength: f64) -> [f64; 3] {
+    static WHITE: OnceLock<[f64; 3]> = OnceLock::new();
+    let white = WHITE.get_or_init(|| {
+        quadrature().fold([0.0; 3], |sum, (wavelength, weight)| {
+            let rgb = xyz_to_linear_srgb(&cie_xyz(wavelength));
+            std::array::from_fn(|i| sum[i] + rgb[i] * d65(wavelength) * weight)
+        })
+    });
+    let rgb = xyz_to_linear_srgb(&cie_xyz(wavelength));
+    std::array::from_fn(|i| rgb[i] / white[i])
+}
+
+/// The value of the brightest channel at each layer of the table, closer
+/// together near 0 and 1 where the fits change fastest.
+pub fn brightness() -> [f64; TABLE_SIZE] {
+    let smoothstep = |x: f64| x * x * (3.0 - 2.0 * x);
+    std::array::from_fn(|k| smoothstep(smoothstep(k as f64 / (TABLE_SIZE - 1) as f64)))
+}
+
+/// Fits the coefficients for every color of the table: by brightest
+/// channel, its value, then the next two channels in order relative to it.
+// only build.rs runs it
+#[allow(dead_code)]
+pub fn fit() -> Vec<[f64; 3]> {
+    let n = TABLE_SIZE;
+    let brightness = brightness();
+    let weights: Vec<(f64, [f64; 3])> = quadrature()
+        .map(|(wavelength, weight)| {
+            let matching = rgb_matching(wavelength);
+            let scale = d65(wavelength) * weight;
+            (normalized(wavelength), matching.map(|m| m * scale))
+        })
+        .collect();
+
+    // each row keeps the other two channels' ratios and walks out from
+    // a middling brightness, starting every fit from the last one
+    let start = n / 5;
+    let fit_row = |row: usize| {
+        let (channel, y, x) = (row / (n * n), row / n % n, row % n);
+        let color = |k: usize| {
+            let z = brightness[k];
+            let mut rgb = [0.0; 3];
+            rgb[channel] = z;
+            rgb[(channel + 1) % 3] = x as f64 / (n - 1) as f64 * z;
+            rgb[(channel + 2) % 3] = y as f64 / (n - 1) as f64 * z;
+            rgb
+        };
+        let mut column = vec![[0.0; 3]; n];
+        let mut coefficients = [0.0; 3];
+        for (k, cell) in column.iter_mut().enumerate().skip(start) {
+            coefficients = gauss_newton(&color(k), coefficients, &weights);
+            *cell = coefficients;
+        }
+        let mut coefficients = column[start];
+        for (k, cell) in column.iter_mut().enumerate().take(start).rev() {
+            coefficients = gauss_newton(&color(k), coefficients, &weights);
+            *cell = coefficients;
+        }
+        column
+    };
+
+    let rows = 3 * n * n;
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let chunk = rows.div_ceil(threads);
+    let mut columns = vec![Vec::new(); rows];
+    std::thread::scope(|scope| {
+        for (first, part) in columns.chunks_mut(chunk).enumerate() {
+            let fit_row = &fit_row;
+            scope.spawn(move || {
+                for (i, column) in part.iter_mut().enumerate() {
+                    *column = fit_row(first * chunk + i);
+                }
+            });
+        }
+    });
+
+    let mut coefficients = vec![[0.0; 3]; 3 * n * n * n];
+    for (row, column) in columns.into_iter().enumerate() {
+        let (channel, y, x) = (row / (n * n), row / n % n, row % n);
+        for (k, value) in column.into_iter().enumerate() {
+            coefficients[((channel * n + k) * n + y) * n + x] = value;
+        }
+    }
+    coefficients
+}
+
+/// Refines `coefficients` until their spectrum reflects `rgb`, where
+/// `weights` give the color each sampled wavelength contributes.
+fn gauss_newton(
+    rgb: &[f64; 3],
+    mut coefficients: [f64; 3],
+    weights: &[(f64, [f64; 3])],
+) -> [f64; 3] {
+    let error = |residual: &[f64; 3]| residual.iter().map(|r| r * r).sum::<f64>();
+    let (mut residual, mut jacobian) = fit_residual(rgb, &coefficients, weights);
+    for _ in 0..30 {
+        if error(&residual) < 1e-12 {
+            break;
+        }
+        let Some(step) = solve(&jacobian, &residual) else {
+            break;
+        };
+        // halve the step until it improves the fit, since a full one can
+        // overshoot badly for colors at the edge of the gamut
+        let mut scale = 1.0;
+        let improved = loop {
+            let mut candidate: [f64; 3] =
+                std::array::from_fn(|i| coefficients[i] + scale * step[i]);
+            // those want infinitely steep spectra
+            let largest = candidate.iter().fold(0.0_f64, |max, c| max.max(c.abs()));
+            if largest > 200.0 {
+                candidate = candidate.map(|c| c * 200.0 / largest);
+            }
+            let (candidate_residual, candidate_jacobian) = fit_residual(rgb, &candidate, weights);
+            if error(&candidate_residual) < error(&residual) {
+                break Some((candidate, candidate_residual, candidate_jacobian));
+            }
+            scale *= 0.5;
+            if scale < 1e-3 {
+                break None;
+            }
+        };
+        let Some(improved) = improved else {
+            break;
+        };
+        (coefficients, residual, jacobian) = improved;
+    }
+    coefficients
+}
+
+/// How far the spectrum for `coefficients` is from reflecting `rgb`, and
+/// the derivatives of its color by each coefficient.
+fn fit_residual(
+    rgb: &[f64; 3],
+    coefficients: &[f64; 3],
+    weights: &[(f64, [f64; 3])],
+) -> ([f64; 3], [[f64; 3]; 3]) {
+    let mut residual = *rgb;
+    let mut jacobian = [[0.0; 3]; 3];
+    for (x, weight) in weights {
+        let p = coefficients[0] * x * x + coefficients[1] * x + coefficients[2];
+        let value = sigmoid(p);
+        let slope = 0.5 / (1.0 + p * p).powf(1.5);
+        for channel in 0..3 {
+            residual[channel] -= weight[channel] * value;
+            for (derivative, basis) in jacobian[channel].iter_mut().zip([x * x, *x, 1.0]) {
+                *derivative += weight[channel] * slope * basis;
+            }
+        }
+    }
+    (residual, jacobian)
+}
+
+/// Solves `a x = b` by Cramer's rule, or `None` if `a` is singular.
+fn solve(a: &[[f64; 3]; 3], b: &[f64; 3]) -> Option<[f64; 3]> {
+    let determinant = |m: &[[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = determinant(a);
+    if d.abs() < 1e-15 {
+        return None;
+    }
+    Some(std::array::from_fn(|column| {
+        let mut m = *a;
+        for row in 0..3 {
+            m[row][column] = b[row];
+        }
+        determinant(&m) / d
+    }))
+}
+
+/// Wavelength mapped to 0..1 over the visible range, which keeps the
+/// coefficients of the fits small.
+pub fn normalized(wavelength: f64) -> f64 {
+    (wavelength - WAVELENGTH_MIN) / (WAVELENGTH_MAX - WAVELENGTH_MIN)
+}
+
+pub fn sigmoid_polynomial(coefficients: &[f64; 3], wavelength: f64) -> f64 {
+    let x = normalized(wavelength);
+    sigmoid(coefficients[0] * x * x + coefficients[1] * x + coefficients[2])
+}
+
+/// Maps the real line onto 0..1.
+fn sigmoid(x: f64) -> f64 {
+    if x.is_infinite() {
+        return if x > 0.0 { 1.0 } else { 0.0 };
+    }
+    0.5 + x / (2.0 * (1.0 + x * x).sqrt())
+}
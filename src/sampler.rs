@@ -0,0 +1,368 @@
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+
+/// Source of sample values for rendering. Values are handed out one
+/// dimension at a time (pixel position, lens position, then light and BSDF
+/// choices for each bounce) and restart at the first dimension for every
+/// pixel sample. Samplers derive everything from the seed, the pixel and the
+/// sample index, so an image comes out the same no matter which thread
+/// renders which pixel, or in what order.
+pub trait Sampler {
+    /// Begins sample `sample_index` of pixel `(i, j)`.
+    fn start_sample(&mut self, i: usize, j: usize, sample_index: usize);
+    /// Next dimension, uniform in `[0, 1)`.
+    fn next_1d(&mut self) -> f64;
+    /// Next two dimensions, which low-discrepancy samplers stratify jointly.
+    fn next_2d(&mut self) -> (f64, f64);
+}
+
+/// The available samplers, chosen per render.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    /// Independent uniform random numbers.
+    #[default]
+    Independent,
+    /// Jittered strata over blocks of 16 samples, shuffled independently for
+    /// every dimension and block.
+    Stratified,
+    /// Halton sequence with a random shift per pixel and dimension.
+    Halton,
+    /// Sobol (0,2) sequence, Owen-scrambled and shuffled per dimension pair.
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn create(&self, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler {
+                pixel: PixelSample::new(seed),
+            }),
+            SamplerKind::Halton => Box::new(HaltonSampler {
+                pixel: PixelSample::new(seed),
+            }),
+            SamplerKind::Sobol => Box::new(SobolSampler {
+                pixel: PixelSample::new(seed),
+            }),
+        }
+    }
+}
+
+/// A PCG-XSH-RR stream per pixel sample.
+pub struct IndependentSampler {
+    seed: u64,
+    state: u64,
+    increment: u64,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        let mut sampler = Self {
+            seed,
+            state: 0,
+            increment: 1,
+        };
+        sampler.start_sample(0, 0, 0);
+        sampler
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(self.increment);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rotation = (old >> 59) as u32;
+        xorshifted.rotate_right(rotation)
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, i: usize, j: usize, sample_index: usize) {
+        let pixel = mix(mix(self.seed) ^ ((j as u64) << 32 | i as u64));
+        self.increment = (pixel << 1) | 1;
+        self.state = 0;
+        self.next_u32();
+        self.state = self.state.wrapping_add(mix(pixel ^ sample_index as u64));
+        self.next_u32();
+    }
+    fn next_1d(&mut self) -> f64 {
+        let bits = (self.next_u32() as u64) << 32 | self.next_u32() as u64;
+        to_unit(bits)
+    }
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+/// Bookkeeping shared by the low-discrepancy samplers, which compute every
+/// value from the pixel, sample index and dimension instead of a stream.
+struct PixelSample {
+    seed: u64,
+    pixel: u64,
+    index: usize,
+    dimension: u64,
+}
+
+impl PixelSample {
+    fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+    fn start(&mut self, i: usize, j: usize, sample_index: usize) {
+        self.pixel = mix(mix(self.seed) ^ ((j as u64) << 32 | i as u64));
+        self.index = sample_index;
+        self.dimension = 0;
+    }
+    /// Hash of the pixel and current dimension, the same for all samples.
+    fn dimension_hash(&self) -> u64 {
+        mix(self.pixel ^ mix(self.dimension))
+    }
+    /// Random value for this sample and dimension.
+    fn random(&self, salt: u64) -> f64 {
+        to_unit(mix(
+            self.dimension_hash() ^ mix(self.index as u64 ^ salt << 40)
+        ))
+    }
+}
+
+/// Samples per block of strata: 16 in 1D, a 4x4 grid in 2D.
+const STRATA: usize = 16;
+const STRATA_SIDE: usize = 4;
+
+/// Stratifies every block of `STRATA` consecutive samples on its own, so
+/// the samples don't depend on how many a pixel takes and a render given
+/// more samples later matches one made in one go. Sample counts that are a
+/// multiple of `STRATA` are stratified fully.
+pub struct StratifiedSampler {
+    pixel: PixelSample,
+}
+
+impl StratifiedSampler {
+    /// This sample's stratum within its block.
+    fn stratum(&self) -> usize {
+        let block = (self.pixel.index / STRATA) as u64;
+        let seed = mix(self.pixel.dimension_hash() ^ block);
+        permute(self.pixel.index % STRATA, STRATA, seed as u32)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, i: usize, j: usize, sample_index: usize) {
+        self.pixel.start(i, j, sample_index);
+    }
+    fn next_1d(&mut self) -> f64 {
+        let value = (self.stratum() as f64 + self.pixel.random(0)) / STRATA as f64;
+        self.pixel.dimension += 1;
+        value
+    }
+    fn next_2d(&mut self) -> (f64, f64) {
+        let stratum = self.stratum();
+        let side = STRATA_SIDE as f64;
+        let x = ((stratum % STRATA_SIDE) as f64 + self.pixel.random(0)) / side;
+        let y = ((stratum / STRATA_SIDE) as f64 + self.pixel.random(1)) / side;
+        self.pixel.dimension += 2;
+        (x, y)
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+pub struct HaltonSampler {
+    pixel: PixelSample,
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, i: usize, j: usize, sample_index: usize) {
+        self.pixel.start(i, j, sample_index);
+    }
+    fn next_1d(&mut self) -> f64 {
+        let dimension = self.pixel.dimension as usize;
+        let value = match PRIMES.get(dimension) {
+            Some(&base) => {
+                // Cranley-Patterson rotation decorrelates neighbouring pixels
+                let shift = to_unit(self.pixel.dimension_hash());
+                let value = radical_inverse(base, self.pixel.index as u64) + shift;
+                value - value.floor()
+            }
+            // past the last base the sequence degrades, plain random is better
+            None => self.pixel.random(0),
+        };
+        self.pixel.dimension += 1;
+        value
+    }
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+pub struct SobolSampler {
+    pixel: PixelSample,
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, i: usize, j: usize, sample_index: usize) {
+        self.pixel.start(i, j, sample_index);
+    }
+    fn next_1d(&mut self) -> f64 {
+        let seed = self.pixel.dimension_hash();
+        let index = nested_uniform_scramble(self.pixel.index as u32, seed as u32);
+        let value = nested_uniform_scramble(index.reverse_bits(), (seed >> 32) as u32);
+        self.pixel.dimension += 1;
+        to_unit32(value)
+    }
+    fn next_2d(&mut self) -> (f64, f64) {
+        // Burley's padding: each pair of dimensions gets its own shuffle of
+        // the same scrambled 2D Sobol points
+        let seed = self.pixel.dimension_hash();
+        let index = nested_uniform_scramble(self.pixel.index as u32, seed as u32);
+        let x = nested_uniform_scramble(index.reverse_bits(), (seed >> 32) as u32);
+        let y = nested_uniform_scramble(sobol_second_dimension(index), mix(seed) as u32);
+        self.pixel.dimension += 2;
+        (to_unit32(x), to_unit32(y))
+    }
+}
+
+/// Point `u` mapped to the unit disk with Shirley's concentric mapping.
+pub fn sample_disk(u: (f64, f64)) -> (f64, f64) {
+    let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (radius, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    (radius * theta.cos(), radius * theta.sin())
+}
+
+/// Cosine-weighted direction around +z.
+pub fn sample_cosine_hemisphere(u: (f64, f64)) -> Vec3 {
+    let (x, y) = sample_disk(u);
+    Vec3::new(x, y, (1.0 - x * x - y * y).max(0.0).sqrt())
+}
+
+/// Uniform direction on the unit sphere.
+pub fn sample_sphere(u: (f64, f64)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+fn radical_inverse(base: u64, mut index: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut reversed = 0u64;
+    let mut scale = 1.0;
+    while index > 0 {
+        reversed = reversed * base + index % base;
+        scale *= inverse_base;
+        index /= base;
+    }
+    (reversed as f64 * scale).min(ONE_MINUS_EPSILON)
+}
+
+/// Second dimension of the Sobol sequence, whose direction numbers follow
+/// from the primitive polynomial x + 1.
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1u32 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Owen scrambling via Burley's hash: each bit is flipped depending on the
+/// bits above it.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+/// Kensler's hash-based permutation: element `index` of a random
+/// permutation of `0..count` chosen by `seed`.
+fn permute(index: usize, count: usize, seed: u32) -> usize {
+    let (l, p) = (count as u32, seed);
+    let mut w = l.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = index as u32;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p) % l) as usize
+}
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+fn to_unit(bits: u64) -> f64 {
+    // 53 random bits fill the mantissa exactly
+    (bits >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+fn to_unit32(bits: u32) -> f64 {
+    bits as f64 * (1.0 / (1u64 << 32) as f64)
+}
+
+/// SplitMix64 finalizer, spreading nearby inputs over the whole range.
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,1372 @@
+use crate::{
+    aperture::{Aperture, ApertureMask, PolygonAperture},
+    bvh::BvhNode,
+    camera::{Background, Camera},
+    hit_record::Hittable,
+    hittable_list::HittableList,
+    light::{Light, LightList},
+    material::Material,
+    medium::{ConstantMedium, Fog, Medium, PhaseFunction},
+    microfacet::{Ggx, Metal},
+    obj::{ObjError, ObjModel},
+    principled::Principled,
+    quad::Quad,
+    render::{AdaptiveSampling, ColorMode, RenderSettings},
+    sampler::SamplerKind,
+    spectrum::{Dispersion, Illuminant, WAVELENGTH_MAX, WAVELENGTH_MIN},
+    sphere::Sphere,
+    texture::{ImageTexture, NoiseStyle, Texture},
+    triangle::Triangle,
+    vec3::Vec3,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// The file is not valid TOML/JSON or does not match the schema. The
+    /// message comes from the parser and includes the line and column.
+    Parse {
+        path: PathBuf,
+        message: String,
+    },
+    /// The file parsed but describes something impossible, e.g. a reference
+    /// to a material that does not exist. `context` says where, like
+    /// `objects[3]` or `materials.glass`.
+    Invalid {
+        path: PathBuf,
+        context: String,
+        message: String,
+    },
+    UnsupportedFormat {
+        path: PathBuf,
+    },
+    Obj(ObjError),
+}
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Parse { path, message } => write!(f, "{}: {}", path.display(), message),
+            SceneError::Invalid {
+                path,
+                context,
+                message,
+            } => write!(f, "{}: {}: {}", path.display(), context, message),
+            SceneError::UnsupportedFormat { path } => write!(
+                f,
+                "{}: unknown scene format, expected a .toml or .json file",
+                path.display()
+            ),
+            SceneError::Obj(err) => write!(f, "{}", err),
+        }
+    }
+}
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Obj(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+impl From<ObjError> for SceneError {
+    fn from(err: ObjError) -> Self {
+        SceneError::Obj(err)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    camera: CameraDesc,
+    #[serde(default)]
+    render: RenderDesc,
+    #[serde(default)]
+    textures: BTreeMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+    fog: Option<FogDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    look_from: [f64; 3],
+    look_at: [f64; 3],
+    #[serde(default = "default_fov")]
+    fov: f64,
+    #[serde(default)]
+    defocus_angle: f64,
+    /// Defaults to the distance between `look_from` and `look_at`.
+    focus_distance: Option<f64>,
+    #[serde(default)]
+    aperture: ApertureDesc,
+    #[serde(default)]
+    cat_eye: f64,
+}
+fn default_fov() -> f64 {
+    90.0
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct RenderDesc {
+    pub width: usize,
+    /// Overrides `aspect_ratio` when set.
+    pub height: Option<usize>,
+    pub aspect_ratio: f64,
+    pub samples_per_pixel: usize,
+    pub max_depth: usize,
+    pub background: BackgroundDesc,
+    /// Seeds both procedural content (random spheres, noise textures) and
+    /// the sampling of the render.
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub color_mode: ColorMode,
+    /// Turns on adaptive sampling: pixels stop once their noise is below
+    /// this, with `samples_per_pixel` as the most any pixel takes.
+    pub noise_threshold: Option<f64>,
+    /// The fewest samples a pixel takes with adaptive sampling.
+    pub min_samples_per_pixel: usize,
+}
+impl Default for RenderDesc {
+    fn default() -> Self {
+        Self {
+            width: 900,
+            height: None,
+            aspect_ratio: 16. / 9.,
+            samples_per_pixel: 100,
+            max_depth: 50,
+            background: BackgroundDesc::Named("sky".to_string()),
+            seed: 0,
+            sampler: SamplerKind::Independent,
+            color_mode: ColorMode::Rgb,
+            noise_threshold: None,
+            min_samples_per_pixel: 16,
+        }
+    }
+}
+
+/// Either `"sky"`, `"black"`, or an RGB color.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum BackgroundDesc {
+    Named(String),
+    Color([f64; 3]),
+}
+
+/// An RGB color, or the name of an entry in `textures`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorOrTexture {
+    Color([f64; 3]),
+    Texture(String),
+}
+
+/// A number, or the name of an entry in `textures` whose channels are
+/// averaged.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrTexture {
+    Number(f64),
+    Texture(String),
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum NoiseStyleDesc {
+    Smooth,
+    Turbulence,
+    #[default]
+    Marble,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MetalDesc {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+/// One of the glass presets, or a dispersion formula with its
+/// coefficients.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DispersionDesc {
+    Glass(GlassDesc),
+    Formula(DispersionFormulaDesc),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum GlassDesc {
+    Bk7,
+    FusedSilica,
+    DenseFlint,
+    Diamond,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum DispersionFormulaDesc {
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+#[derive(Deserialize, Default)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ApertureDesc {
+    #[default]
+    Circle,
+    Polygon {
+        blades: usize,
+        #[serde(default)]
+        rotation: f64,
+    },
+    /// A grayscale PNG or JPEG, relative to the scene file.
+    Image { path: PathBuf },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid {
+        color: [f64; 3],
+    },
+    Checker {
+        scale: f64,
+        even: ColorOrTexture,
+        odd: ColorOrTexture,
+    },
+    /// A PNG or JPEG file, relative to the scene file.
+    Image {
+        path: PathBuf,
+    },
+    Noise {
+        #[serde(default = "default_noise_scale")]
+        scale: f64,
+        #[serde(default)]
+        style: NoiseStyleDesc,
+    },
+}
+fn default_noise_scale() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: ColorOrTexture,
+    },
+    Metal {
+        albedo: ColorOrTexture,
+        fuzz: f64,
+    },
+    /// Glass, with `refraction_index` or else `dispersion` for an index that
+    /// changes with wavelength. `absorption` is the fraction of light
+    /// absorbed per unit of distance inside, per channel.
+    Dielectric {
+        refraction_index: Option<f64>,
+        absorption: Option<[f64; 3]>,
+        dispersion: Option<DispersionDesc>,
+    },
+    /// Gives off `emit`, as the light of a black body at `temperature`
+    /// kelvin if given or else white.
+    DiffuseLight {
+        emit: [f64; 3],
+        temperature: Option<f64>,
+    },
+    /// A metal given either by `metal`, one of the presets, or by the real
+    /// and imaginary parts of its index of refraction, `eta` and `k`.
+    Conductor {
+        metal: Option<MetalDesc>,
+        eta: Option<[f64; 3]>,
+        k: Option<[f64; 3]>,
+        #[serde(default)]
+        roughness: f64,
+    },
+    RoughDielectric {
+        refraction_index: f64,
+        roughness: f64,
+        absorption: Option<[f64; 3]>,
+    },
+    /// See `principled::Principled`; parameters left out get its defaults.
+    Principled {
+        base_color: Option<ColorOrTexture>,
+        metallic: Option<NumberOrTexture>,
+        roughness: Option<NumberOrTexture>,
+        specular: Option<NumberOrTexture>,
+        specular_tint: Option<NumberOrTexture>,
+        sheen: Option<NumberOrTexture>,
+        clearcoat: Option<NumberOrTexture>,
+        clearcoat_roughness: Option<NumberOrTexture>,
+        transmission: Option<NumberOrTexture>,
+        ior: Option<NumberOrTexture>,
+        anisotropy: Option<NumberOrTexture>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: String,
+    },
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        normals: Option<[[f64; 3]; 3]>,
+        material: String,
+    },
+    /// Parallelogram with corner `q` and edges `u` and `v`.
+    Quad {
+        q: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+        material: String,
+    },
+    /// Axis-aligned box made of six quads.
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: String,
+    },
+    /// A Wavefront OBJ file, relative to the scene file. Without `material`
+    /// the faces keep their MTL materials.
+    Mesh {
+        path: PathBuf,
+        material: Option<String>,
+    },
+    /// The grid of small random spheres from the book's cover scene, on
+    /// `-range..range` in x and z.
+    RandomSpheres { range: i32 },
+    /// Smoke or mist filling `boundary`, with `density` collisions per unit
+    /// of distance. `albedo` is the fraction of light scattered rather than
+    /// absorbed at each, white if left out.
+    ConstantMedium {
+        boundary: BoundaryDesc,
+        density: f64,
+        albedo: Option<[f64; 3]>,
+        phase: Option<PhaseDesc>,
+    },
+}
+
+/// The shape of a `constant_medium`.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BoundaryDesc {
+    Sphere { center: [f64; 3], radius: f64 },
+    Box { min: [f64; 3], max: [f64; 3] },
+}
+
+/// How a medium scatters light; isotropic if left out.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum PhaseDesc {
+    Isotropic,
+    /// `g` between -1 and 1: positive scatters light onwards, negative back.
+    HenyeyGreenstein {
+        g: f64,
+    },
+}
+
+/// Fog around the camera, out to `radius`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FogDesc {
+    density: f64,
+    albedo: Option<[f64; 3]>,
+    phase: Option<PhaseDesc>,
+    #[serde(default = "default_fog_radius")]
+    radius: f64,
+}
+fn default_fog_radius() -> f64 {
+    100.0
+}
+impl ObjectDesc {
+    fn kind(&self) -> &'static str {
+        match self {
+            ObjectDesc::Sphere { .. } => "sphere",
+            ObjectDesc::Triangle { .. } => "triangle",
+            ObjectDesc::Quad { .. } => "quad",
+            ObjectDesc::Box { .. } => "box",
+            ObjectDesc::Mesh { .. } => "mesh",
+            ObjectDesc::RandomSpheres { .. } => "random_spheres",
+            ObjectDesc::ConstantMedium { .. } => "constant_medium",
+        }
+    }
+}
+
+/// An emitter; shorthand for an object with a `diffuse_light` material.
+/// `emission` is the emitted radiance, tinting a black body at
+/// `temperature` if given.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        emission: [f64; 3],
+        temperature: Option<f64>,
+    },
+    Quad {
+        q: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+        emission: [f64; 3],
+        temperature: Option<f64>,
+    },
+}
+
+pub struct Scene {
+    pub camera: Camera,
+    /// Everything rays can hit, in a bounding volume hierarchy.
+    pub world: Box<dyn Hittable>,
+    /// Emissive spheres and quads, also present in `world`, for direct
+    /// light sampling.
+    pub lights: LightList,
+    /// How many objects went into `world`; a mesh counts as one.
+    pub object_count: usize,
+    /// How the scene file says to render it. `render` takes the settings
+    /// separately, so these are only a suggestion.
+    pub settings: RenderSettings,
+}
+
+/// Settings given on the command line that take precedence over the scene
+/// file.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LoadOptions {
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples_per_pixel: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub seed: Option<u64>,
+    pub sampler: Option<SamplerKind>,
+    pub color_mode: Option<ColorMode>,
+    pub noise_threshold: Option<f64>,
+    pub min_samples_per_pixel: Option<usize>,
+}
+
+impl RenderDesc {
+    fn apply(&mut self, options: &LoadOptions) {
+        if let Some(width) = options.width {
+            // keep the file's shape unless a height is given as well
+            if let Some(height) = self.height.take() {
+                if height > 0 {
+                    self.aspect_ratio = self.width as f64 / height as f64;
+                }
+            }
+            self.width = width;
+        }
+        if let Some(height) = options.height {
+            self.height = Some(height);
+        }
+        if let Some(samples_per_pixel) = options.samples_per_pixel {
+            self.samples_per_pixel = samples_per_pixel;
+        }
+        if let Some(max_depth) = options.max_depth {
+            self.max_depth = max_depth;
+        }
+        if let Some(seed) = options.seed {
+            self.seed = seed;
+        }
+        if let Some(sampler) = options.sampler {
+            self.sampler = sampler;
+        }
+        if let Some(color_mode) = options.color_mode {
+            self.color_mode = color_mode;
+        }
+        if let Some(noise_threshold) = options.noise_threshold {
+            self.noise_threshold = Some(noise_threshold);
+        }
+        if let Some(min_samples_per_pixel) = options.min_samples_per_pixel {
+            self.min_samples_per_pixel = min_samples_per_pixel;
+        }
+    }
+}
+
+impl Scene {
+    /// A scene of `objects` seen through `camera`. Emissive objects that
+    /// should be sampled as lights have to be in both `objects` and
+    /// `lights`.
+    pub fn new(camera: Camera, objects: HittableList, lights: LightList) -> Self {
+        let object_count = objects.objects.len();
+        Self {
+            camera,
+            world: BvhNode::from_list(objects),
+            lights,
+            object_count,
+            settings: RenderSettings::default(),
+        }
+    }
+
+    /// Loads a scene description, picking the format from the extension
+    /// (`.toml` or `.json`).
+    pub fn load(path: &Path) -> Result<Self, SceneError> {
+        Self::load_with(path, &LoadOptions::default())
+    }
+
+    pub fn load_with(path: &Path, options: &LoadOptions) -> Result<Self, SceneError> {
+        let source = fs::read_to_string(path).map_err(|source| SceneError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::parse(&source, path, options)
+    }
+
+    /// Builds a scene from the contents of a scene file. `path` is where the
+    /// file lives: its extension gives the format, and meshes and images it
+    /// refers to are looked up next to it.
+    pub fn parse(source: &str, path: &Path, options: &LoadOptions) -> Result<Self, SceneError> {
+        let parse_err = |message: String| SceneError::Parse {
+            path: path.to_path_buf(),
+            message,
+        };
+        let mut file: SceneFile = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(source).map_err(|e| parse_err(e.to_string()))?,
+            Some("json") => serde_json::from_str(source).map_err(|e| parse_err(e.to_string()))?,
+            _ => {
+                return Err(SceneError::UnsupportedFormat {
+                    path: path.to_path_buf(),
+                })
+            }
+        };
+        file.render.apply(options);
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        SceneBuilder {
+            path,
+            base_dir,
+            seed: file.render.seed,
+        }
+        .build(file)
+    }
+}
+
+struct SceneBuilder<'a> {
+    path: &'a Path,
+    base_dir: &'a Path,
+    seed: u64,
+}
+
+impl SceneBuilder<'_> {
+    fn invalid(&self, context: impl Into<String>, message: impl Into<String>) -> SceneError {
+        SceneError::Invalid {
+            path: self.path.to_path_buf(),
+            context: context.into(),
+            message: message.into(),
+        }
+    }
+
+    fn build(&self, file: SceneFile) -> Result<Scene, SceneError> {
+        let camera = self.build_camera(&file.camera, &file.render)?;
+        let settings = self.build_settings(&file.render)?;
+
+        let mut textures = BTreeMap::new();
+        for name in file.textures.keys() {
+            self.build_texture(name, &file.textures, &mut textures, &mut Vec::new())?;
+        }
+
+        let mut materials = BTreeMap::new();
+        for (name, desc) in &file.materials {
+            materials.insert(name.as_str(), self.build_material(name, desc, &textures)?);
+        }
+
+        let mut world = HittableList::new();
+        let mut lights = LightList::new();
+        for (i, object) in file.objects.iter().enumerate() {
+            let context = format!("objects[{}] ({})", i, object.kind());
+            let lookup = |name: &str| -> Result<Material, SceneError> {
+                materials.get(name).cloned().ok_or_else(|| {
+                    let known: Vec<&str> = materials.keys().copied().collect();
+                    self.invalid(
+                        context.clone(),
+                        format!(
+                            "unknown material `{}` (defined: {})",
+                            name,
+                            if known.is_empty() {
+                                "none".to_string()
+                            } else {
+                                known.join(", ")
+                            }
+                        ),
+                    )
+                })
+            };
+
+            match object {
+                ObjectDesc::Sphere {
+                    center,
+                    radius,
+                    material,
+                } => {
+                    if !is_positive(*radius) {
+                        return Err(self.invalid(context, "radius must be positive"));
+                    }
+                    let sphere = Sphere::new(&to_vec3(center), *radius, lookup(material)?);
+                    if is_emissive(&sphere.material) {
+                        lights.push(Light::Sphere(sphere.clone()));
+                    }
+                    world.push(Box::new(sphere));
+                }
+                ObjectDesc::Triangle {
+                    vertices,
+                    normals,
+                    material,
+                } => {
+                    let mut triangle = Triangle::new(
+                        &to_vec3(&vertices[0]),
+                        &to_vec3(&vertices[1]),
+                        &to_vec3(&vertices[2]),
+                        lookup(material)?,
+                    );
+                    if let Some(n) = normals {
+                        triangle =
+                            triangle.with_normals([to_vec3(&n[0]), to_vec3(&n[1]), to_vec3(&n[2])]);
+                    }
+                    world.push(Box::new(triangle));
+                }
+                ObjectDesc::Quad { q, u, v, material } => {
+                    let (u, v) = (to_vec3(u), to_vec3(v));
+                    if Vec3::cross(&u, &v).near_zero() {
+                        return Err(self.invalid(context, "u and v must not be parallel"));
+                    }
+                    let quad = Quad::new(&to_vec3(q), &u, &v, lookup(material)?);
+                    if is_emissive(&quad.material) {
+                        lights.push(Light::Quad(quad.clone()));
+                    }
+                    world.push(Box::new(quad));
+                }
+                ObjectDesc::Box { min, max, material } => {
+                    if (0..3).any(|i| !is_positive(max[i] - min[i])) {
+                        return Err(self.invalid(context, "min must be below max on every axis"));
+                    }
+                    push_box(&mut world, &to_vec3(min), &to_vec3(max), lookup(material)?);
+                }
+                ObjectDesc::Mesh { path, material } => {
+                    let obj_path = self.base_dir.join(path);
+                    let model = match material {
+                        Some(name) => ObjModel::load_with_material(&obj_path, lookup(name)?)?,
+                        None => ObjModel::load(&obj_path)?,
+                    };
+                    for obj_mesh in model.meshes {
+                        world.push(Box::new(obj_mesh.mesh));
+                    }
+                }
+                ObjectDesc::RandomSpheres { range } => {
+                    if *range < 0 {
+                        return Err(self.invalid(context, "range must not be negative"));
+                    }
+                    push_random_spheres(&mut world, *range, &mut StdRng::seed_from_u64(self.seed));
+                }
+                ObjectDesc::ConstantMedium {
+                    boundary,
+                    density,
+                    albedo,
+                    phase,
+                } => {
+                    let medium = self.build_medium(&context, *density, albedo, phase)?;
+                    // the boundary's own material is never looked at
+                    let boundary: Box<dyn Hittable> = match boundary {
+                        BoundaryDesc::Sphere { center, radius } => {
+                            if !is_positive(*radius) {
+                                return Err(self.invalid(context, "radius must be positive"));
+                            }
+                            Box::new(Sphere::new(
+                                &to_vec3(center),
+                                *radius,
+                                Material::Medium(medium),
+                            ))
+                        }
+                        BoundaryDesc::Box { min, max } => {
+                            if (0..3).any(|i| !is_positive(max[i] - min[i])) {
+                                return Err(
+                                    self.invalid(context, "min must be below max on every axis")
+                                );
+                            }
+                            let mut sides = HittableList::new();
+                            push_box(
+                                &mut sides,
+                                &to_vec3(min),
+                                &to_vec3(max),
+                                Material::Medium(medium),
+                            );
+                            Box::new(sides)
+                        }
+                    };
+                    world.push(Box::new(ConstantMedium::new(boundary, medium)));
+                }
+            }
+        }
+
+        for (i, light) in file.lights.iter().enumerate() {
+            let context = format!("lights[{}]", i);
+            match light {
+                LightDesc::Sphere {
+                    center,
+                    radius,
+                    emission,
+                    temperature,
+                } => {
+                    if !is_positive(*radius) {
+                        return Err(self.invalid(context, "radius must be positive"));
+                    }
+                    let material = self.light_material(&context, emission, *temperature)?;
+                    let sphere = Sphere::new(&to_vec3(center), *radius, material);
+                    lights.push(Light::Sphere(sphere.clone()));
+                    world.push(Box::new(sphere));
+                }
+                LightDesc::Quad {
+                    q,
+                    u,
+                    v,
+                    emission,
+                    temperature,
+                } => {
+                    let (u, v) = (to_vec3(u), to_vec3(v));
+                    if Vec3::cross(&u, &v).near_zero() {
+                        return Err(self.invalid(context, "u and v must not be parallel"));
+                    }
+                    let material = self.light_material(&context, emission, *temperature)?;
+                    let quad = Quad::new(&to_vec3(q), &u, &v, material);
+                    lights.push(Light::Quad(quad.clone()));
+                    world.push(Box::new(quad));
+                }
+            }
+        }
+
+        let mut scene = Scene::new(camera, world, lights);
+        if let Some(fog) = &file.fog {
+            if !is_positive(fog.radius) {
+                return Err(self.invalid("fog.radius", "must be positive"));
+            }
+            scene.camera.fog = Some(Fog {
+                medium: self.build_medium("fog", fog.density, &fog.albedo, &fog.phase)?,
+                radius: fog.radius,
+            });
+        }
+        scene.settings = settings;
+        Ok(scene)
+    }
+
+    fn build_settings(&self, render: &RenderDesc) -> Result<RenderSettings, SceneError> {
+        if render.samples_per_pixel == 0 {
+            return Err(self.invalid("render.samples_per_pixel", "must be positive"));
+        }
+        if render.max_depth == 0 {
+            return Err(self.invalid("render.max_depth", "must be positive"));
+        }
+        let adaptive = match render.noise_threshold {
+            Some(noise_threshold) => {
+                if !is_positive(noise_threshold) {
+                    return Err(self.invalid("render.noise_threshold", "must be positive"));
+                }
+                if render.min_samples_per_pixel < 2 {
+                    return Err(self.invalid("render.min_samples_per_pixel", "must be at least 2"));
+                }
+                Some(AdaptiveSampling {
+                    min_samples: render.min_samples_per_pixel,
+                    noise_threshold,
+                })
+            }
+            None => None,
+        };
+        Ok(RenderSettings {
+            samples_per_pixel: render.samples_per_pixel,
+            max_depth: render.max_depth,
+            seed: render.seed,
+            sampler: render.sampler,
+            color_mode: render.color_mode,
+            adaptive,
+            ..RenderSettings::default()
+        })
+    }
+
+    fn build_camera(&self, desc: &CameraDesc, render: &RenderDesc) -> Result<Camera, SceneError> {
+        let look_from = to_vec3(&desc.look_from);
+        let look_at = to_vec3(&desc.look_at);
+        let view_distance = Vec3::sub(&look_from, &look_at).length();
+        if view_distance == 0.0 {
+            return Err(self.invalid("camera", "look_from and look_at must differ"));
+        }
+        // the camera keeps +y up, which a vertical view leaves undefined
+        let view = Vec3::unit(&Vec3::sub(&look_from, &look_at));
+        if Vec3::cross(&Vec3::new(0.0, 1.0, 0.0), &view).near_zero() {
+            return Err(self.invalid(
+                "camera",
+                "look_from and look_at must not be straight above each other",
+            ));
+        }
+        if !(desc.fov > 0.0 && desc.fov < 180.0) {
+            return Err(self.invalid("camera.fov", "must be between 0 and 180 degrees"));
+        }
+        if !is_non_negative(desc.defocus_angle) {
+            return Err(self.invalid("camera.defocus_angle", "must not be negative"));
+        }
+        let focus_distance = desc.focus_distance.unwrap_or(view_distance);
+        if !is_positive(focus_distance) {
+            return Err(self.invalid("camera.focus_distance", "must be positive"));
+        }
+
+        if render.width == 0 {
+            return Err(self.invalid("render.width", "must be positive"));
+        }
+        let aspect_ratio = match render.height {
+            Some(0) => return Err(self.invalid("render.height", "must be positive")),
+            Some(height) => render.width as f64 / height as f64,
+            None => render.aspect_ratio,
+        };
+        if !is_positive(aspect_ratio) {
+            return Err(self.invalid("render.aspect_ratio", "must be positive"));
+        }
+        let mut camera = Camera::builder()
+            .image_width(render.width)
+            .aspect_ratio(aspect_ratio)
+            .look_from(look_from)
+            .look_at(look_at)
+            .fov(desc.fov)
+            .defocus_angle(desc.defocus_angle)
+            .focus_dist(focus_distance)
+            .build();
+        camera.aperture = match &desc.aperture {
+            ApertureDesc::Circle => Aperture::Circle,
+            ApertureDesc::Polygon { blades, rotation } => {
+                let polygon = PolygonAperture::new(*blades, *rotation)
+                    .map_err(|_| self.invalid("camera.aperture.blades", "must be at least 3"))?;
+                Aperture::Polygon(polygon)
+            }
+            ApertureDesc::Image { path } => {
+                let image_path = self.base_dir.join(path);
+                let mask = ApertureMask::load(&image_path).map_err(|e| {
+                    self.invalid(
+                        "camera.aperture",
+                        format!("cannot load {}: {}", image_path.display(), e),
+                    )
+                })?;
+                Aperture::Mask(Arc::new(mask))
+            }
+        };
+        if !is_non_negative(desc.cat_eye) {
+            return Err(self.invalid("camera.cat_eye", "must not be negative"));
+        }
+        camera.cat_eye = desc.cat_eye;
+        camera.background = match &render.background {
+            BackgroundDesc::Named(name) if name == "sky" => Background::Sky,
+            BackgroundDesc::Named(name) if name == "black" => Background::Color(Vec3::default()),
+            BackgroundDesc::Named(name) => {
+                return Err(self.invalid(
+                    "render.background",
+                    format!(
+                        "unknown background `{}`, expected \"sky\", \"black\" or a color",
+                        name
+                    ),
+                ))
+            }
+            BackgroundDesc::Color(color) => {
+                if !color.iter().all(|c| c.is_finite() && *c >= 0.0) {
+                    return Err(
+                        self.invalid("render.background", "color components must be non-negative")
+                    );
+                }
+                Background::Color(to_vec3(color))
+            }
+        };
+        Ok(camera)
+    }
+
+    fn light_material(
+        &self,
+        context: &str,
+        emission: &[f64; 3],
+        temperature: Option<f64>,
+    ) -> Result<Material, SceneError> {
+        if !emission.iter().all(|c| c.is_finite() && *c >= 0.0) {
+            return Err(self.invalid(context, "emission components must be non-negative"));
+        }
+        let illuminant = match temperature {
+            Some(temperature) if (1000.0..=40000.0).contains(&temperature) => {
+                Illuminant::blackbody(temperature)
+            }
+            Some(_) => {
+                return Err(self.invalid(context, "temperature must be between 1000 and 40000"))
+            }
+            None => Illuminant::D65,
+        };
+        Ok(Material::DiffuseLight {
+            emit: to_vec3(emission),
+            illuminant,
+        })
+    }
+
+    /// Builds texture `name` into `done`, building the textures it refers to
+    /// first. `visiting` holds the chain of references being resolved so
+    /// cycles can be reported.
+    fn build_texture(
+        &self,
+        name: &str,
+        descs: &BTreeMap<String, TextureDesc>,
+        done: &mut BTreeMap<String, Texture>,
+        visiting: &mut Vec<String>,
+    ) -> Result<Texture, SceneError> {
+        if let Some(texture) = done.get(name) {
+            return Ok(texture.clone());
+        }
+        let context = format!("textures.{}", name);
+        if visiting.iter().any(|n| n == name) {
+            visiting.push(name.to_string());
+            return Err(self.invalid(
+                context,
+                format!("texture references itself ({})", visiting.join(" -> ")),
+            ));
+        }
+        let desc = &descs[name];
+
+        visiting.push(name.to_string());
+        let texture = match desc {
+            TextureDesc::Solid { color } => Texture::Solid(self.check_color(&context, color)?),
+            TextureDesc::Checker { scale, even, odd } => {
+                if !is_positive(*scale) {
+                    return Err(self.invalid(context, "scale must be positive"));
+                }
+                let mut resolve = |entry: &ColorOrTexture| match entry {
+                    ColorOrTexture::Color(color) => {
+                        Ok(Texture::Solid(self.check_color(&context, color)?))
+                    }
+                    ColorOrTexture::Texture(other) => {
+                        if !descs.contains_key(other) {
+                            return Err(self.unknown_texture(&context, other, descs.keys()));
+                        }
+                        self.build_texture(other, descs, done, visiting)
+                    }
+                };
+                let even = resolve(even)?;
+                let odd = resolve(odd)?;
+                Texture::checker(*scale, even, odd)
+            }
+            TextureDesc::Image { path } => {
+                let image_path = self.base_dir.join(path);
+                let image = ImageTexture::load(&image_path).map_err(|e| {
+                    self.invalid(
+                        context.clone(),
+                        format!("cannot load {}: {}", image_path.display(), e),
+                    )
+                })?;
+                Texture::Image(Arc::new(image))
+            }
+            TextureDesc::Noise { scale, style } => {
+                if !is_positive(*scale) {
+                    return Err(self.invalid(context, "scale must be positive"));
+                }
+                let style = match style {
+                    NoiseStyleDesc::Smooth => NoiseStyle::Smooth,
+                    NoiseStyleDesc::Turbulence => NoiseStyle::Turbulence,
+                    NoiseStyleDesc::Marble => NoiseStyle::Marble,
+                };
+                Texture::noise(*scale, style, &mut StdRng::seed_from_u64(self.seed))
+            }
+        };
+        visiting.pop();
+
+        done.insert(name.to_string(), texture.clone());
+        Ok(texture)
+    }
+
+    fn unknown_texture<'n>(
+        &self,
+        context: &str,
+        name: &str,
+        known: impl Iterator<Item = &'n String>,
+    ) -> SceneError {
+        let known: Vec<&str> = known.map(|k| k.as_str()).collect();
+        self.invalid(
+            context,
+            format!(
+                "unknown texture `{}` (defined: {})",
+                name,
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            ),
+        )
+    }
+
+    fn check_color(&self, context: &str, color: &[f64; 3]) -> Result<Vec3, SceneError> {
+        if color.iter().all(|c| c.is_finite() && *c >= 0.0) {
+            Ok(to_vec3(color))
+        } else {
+            Err(self.invalid(context, "color components must be non-negative"))
+        }
+    }
+
+    fn build_material(
+        &self,
+        name: &str,
+        desc: &MaterialDesc,
+        textures: &BTreeMap<String, Texture>,
+    ) -> Result<Material, SceneError> {
+        let context = || format!("materials.{}", name);
+        let check_albedo = |albedo: &ColorOrTexture| match albedo {
+            ColorOrTexture::Color(color) => {
+                Ok(Texture::Solid(self.check_color(&context(), color)?))
+            }
+            ColorOrTexture::Texture(texture) => textures
+                .get(texture)
+                .cloned()
+                .ok_or_else(|| self.unknown_texture(&context(), texture, textures.keys())),
+        };
+        match desc {
+            MaterialDesc::Lambertian { albedo } => Ok(Material::Lambertian {
+                albedo: check_albedo(albedo)?,
+            }),
+            MaterialDesc::Metal { albedo, fuzz } => {
+                if !(0.0..=1.0).contains(fuzz) {
+                    return Err(self.invalid(context(), "fuzz must be between 0 and 1"));
+                }
+                Ok(Material::Metal {
+                    albedo: check_albedo(albedo)?,
+                    fuzz: *fuzz,
+                })
+            }
+            MaterialDesc::DiffuseLight { emit, temperature } => {
+                self.light_material(&context(), emit, *temperature)
+            }
+            MaterialDesc::Dielectric {
+                refraction_index,
+                absorption,
+                dispersion,
+            } => {
+                let (refraction_index, dispersion) = match (refraction_index, dispersion) {
+                    (Some(refraction_index), None) => {
+                        if !is_positive(*refraction_index) {
+                            return Err(
+                                self.invalid(context(), "refraction_index must be positive")
+                            );
+                        }
+                        (*refraction_index, None)
+                    }
+                    (None, Some(dispersion)) => {
+                        let dispersion = self.build_dispersion(&context(), dispersion)?;
+                        // the index at the helium d line, the one glass
+                        // catalogs quote
+                        (dispersion.refraction_index(587.56), Some(dispersion))
+                    }
+                    _ => {
+                        return Err(self
+                            .invalid(context(), "give either `refraction_index` or `dispersion`"))
+                    }
+                };
+                Ok(Material::Dielectric {
+                    refraction_index,
+                    absorption: self.check_absorption(&context(), absorption)?,
+                    dispersion,
+                })
+            }
+            MaterialDesc::Conductor {
+                metal,
+                eta,
+                k,
+                roughness,
+            } => {
+                let (eta, k) = match (metal, eta, k) {
+                    (Some(metal), None, None) => {
+                        let metal = match metal {
+                            MetalDesc::Gold => Metal::Gold,
+                            MetalDesc::Copper => Metal::Copper,
+                            MetalDesc::Aluminium => Metal::Aluminium,
+                            MetalDesc::Silver => Metal::Silver,
+                        };
+                        metal.ior()
+                    }
+                    (None, Some(eta), Some(k)) => {
+                        if !eta.iter().all(|c| is_positive(*c)) {
+                            return Err(self.invalid(context(), "eta components must be positive"));
+                        }
+                        (to_vec3(eta), self.check_color(&context(), k)?)
+                    }
+                    _ => {
+                        return Err(
+                            self.invalid(context(), "give either `metal` or both `eta` and `k`")
+                        )
+                    }
+                };
+                Ok(Material::Conductor {
+                    eta,
+                    k,
+                    microfacet: self.check_roughness(&context(), *roughness)?,
+                })
+            }
+            MaterialDesc::RoughDielectric {
+                refraction_index,
+                roughness,
+                absorption,
+            } => {
+                if !is_positive(*refraction_index) {
+                    return Err(self.invalid(context(), "refraction_index must be positive"));
+                }
+                Ok(Material::RoughDielectric {
+                    refraction_index: *refraction_index,
+                    microfacet: self.check_roughness(&context(), *roughness)?,
+                    absorption: self.check_absorption(&context(), absorption)?,
+                })
+            }
+            MaterialDesc::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                specular_tint,
+                sheen,
+                clearcoat,
+                clearcoat_roughness,
+                transmission,
+                ior,
+                anisotropy,
+            } => {
+                let defaults = Principled::default();
+                let check_parameter =
+                    |name: &str, value: &Option<NumberOrTexture>, default| match value {
+                        None => Ok(default),
+                        Some(NumberOrTexture::Number(x)) if (0.0..=1.0).contains(x) => {
+                            Ok(Texture::Solid(Vec3::new(*x, *x, *x)))
+                        }
+                        Some(NumberOrTexture::Number(_)) => {
+                            Err(self
+                                .invalid(context(), format!("{} must be between 0 and 1", name)))
+                        }
+                        Some(NumberOrTexture::Texture(texture)) => {
+                            textures.get(texture).cloned().ok_or_else(|| {
+                                self.unknown_texture(&context(), texture, textures.keys())
+                            })
+                        }
+                    };
+                let ior = match ior {
+                    None => defaults.ior,
+                    Some(NumberOrTexture::Number(x)) if is_positive(*x) => {
+                        Texture::Solid(Vec3::new(*x, *x, *x))
+                    }
+                    Some(NumberOrTexture::Number(_)) => {
+                        return Err(self.invalid(context(), "ior must be positive"))
+                    }
+                    Some(NumberOrTexture::Texture(texture)) => {
+                        textures.get(texture).cloned().ok_or_else(|| {
+                            self.unknown_texture(&context(), texture, textures.keys())
+                        })?
+                    }
+                };
+                Ok(Material::Principled(Arc::new(Principled {
+                    base_color: match base_color {
+                        Some(color) => check_albedo(color)?,
+                        None => defaults.base_color,
+                    },
+                    metallic: check_parameter("metallic", metallic, defaults.metallic)?,
+                    roughness: check_parameter("roughness", roughness, defaults.roughness)?,
+                    specular: check_parameter("specular", specular, defaults.specular)?,
+                    specular_tint: check_parameter(
+                        "specular_tint",
+                        specular_tint,
+                        defaults.specular_tint,
+                    )?,
+                    sheen: check_parameter("sheen", sheen, defaults.sheen)?,
+                    clearcoat: check_parameter("clearcoat", clearcoat, defaults.clearcoat)?,
+                    clearcoat_roughness: check_parameter(
+                        "clearcoat_roughness",
+                        clearcoat_roughness,
+                        defaults.clearcoat_roughness,
+                    )?,
+                    transmission: check_parameter(
+                        "transmission",
+                        transmission,
+                        defaults.transmission,
+                    )?,
+                    ior,
+                    anisotropy: check_parameter("anisotropy", anisotropy, defaults.anisotropy)?,
+                })))
+            }
+        }
+    }
+
+    fn build_medium(
+        &self,
+        context: &str,
+        density: f64,
+        albedo: &Option<[f64; 3]>,
+        phase: &Option<PhaseDesc>,
+    ) -> Result<Medium, SceneError> {
+        if !is_positive(density) {
+            return Err(self.invalid(context, "density must be positive"));
+        }
+        let albedo = albedo.unwrap_or([1.0; 3]);
+        if !albedo.iter().all(|c| (0.0..=1.0).contains(c)) {
+            return Err(self.invalid(context, "albedo components must be between 0 and 1"));
+        }
+        let phase = match phase {
+            None | Some(PhaseDesc::Isotropic) => PhaseFunction::Isotropic,
+            Some(PhaseDesc::HenyeyGreenstein { g }) if g.abs() < 1.0 => {
+                PhaseFunction::HenyeyGreenstein { g: *g }
+            }
+            Some(PhaseDesc::HenyeyGreenstein { .. }) => {
+                return Err(self.invalid(context, "g must be between -1 and 1"))
+            }
+        };
+        Ok(Medium {
+            density,
+            albedo: to_vec3(&albedo),
+            phase,
+        })
+    }
+
+    fn check_absorption(
+        &self,
+        context: &str,
+        absorption: &Option<[f64; 3]>,
+    ) -> Result<Vec3, SceneError> {
+        match absorption {
+            None => Ok(Vec3::default()),
+            Some(absorption) if absorption.iter().all(|c| c.is_finite() && *c >= 0.0) => {
+                Ok(to_vec3(absorption))
+            }
+            Some(_) => Err(self.invalid(context, "absorption components must be non-negative")),
+        }
+    }
+
+    fn build_dispersion(
+        &self,
+        context: &str,
+        desc: &DispersionDesc,
+    ) -> Result<Dispersion, SceneError> {
+        let dispersion = match desc {
+            DispersionDesc::Glass(GlassDesc::Bk7) => Dispersion::BK7,
+            DispersionDesc::Glass(GlassDesc::FusedSilica) => Dispersion::FUSED_SILICA,
+            DispersionDesc::Glass(GlassDesc::DenseFlint) => Dispersion::DENSE_FLINT,
+            DispersionDesc::Glass(GlassDesc::Diamond) => Dispersion::DIAMOND,
+            DispersionDesc::Formula(DispersionFormulaDesc::Cauchy { a, b }) => {
+                Dispersion::Cauchy { a: *a, b: *b }
+            }
+            DispersionDesc::Formula(DispersionFormulaDesc::Sellmeier { b, c }) => {
+                Dispersion::Sellmeier { b: *b, c: *c }
+            }
+        };
+        // a formula with a pole or a square root of a negative number in
+        // the visible range is a typo in the coefficients
+        let mut wavelength = WAVELENGTH_MIN;
+        while wavelength <= WAVELENGTH_MAX {
+            let index = dispersion.refraction_index(wavelength);
+            if !(index.is_finite() && index >= 1.0) {
+                return Err(self.invalid(
+                    context,
+                    format!(
+                        "dispersion gives an index of refraction of {} at {} nm",
+                        index, wavelength
+                    ),
+                ));
+            }
+            wavelength += 10.0;
+        }
+        Ok(dispersion)
+    }
+
+    fn check_roughness(&self, context: &str, roughness: f64) -> Result<Ggx, SceneError> {
+        if (0.0..=1.0).contains(&roughness) {
+            Ok(Ggx::new(roughness))
+        } else {
+            Err(self.invalid(context, "roughness must be between 0 and 1"))
+        }
+    }
+}
+
+fn is_emissive(material: &Material) -> bool {
+    matches!(material, Material::DiffuseLight { .. })
+}
+
+// also rejects NaN, which a plain `<= 0.0` check would let through
+fn is_positive(x: f64) -> bool {
+    x > 0.0
+}
+
+fn is_non_negative(x: f64) -> bool {
+    x >= 0.0
+}
+
+fn to_vec3(v: &[f64; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+fn push_box(world: &mut HittableList, min: &Vec3, max: &Vec3, material: Material) {
+    let dx = Vec3::new(max.x - min.x, 0., 0.);
+    let dy = Vec3::new(0., max.y - min.y, 0.);
+    let dz = Vec3::new(0., 0., max.z - min.z);
+    let neg = |v: &Vec3| Vec3::mul(v, -1.0);
+
+    // outward-facing sides: front, right, back, left, top, bottom
+    let sides = [
+        (Vec3::new(min.x, min.y, max.z), dx, dy),
+        (Vec3::new(max.x, min.y, max.z), neg(&dz), dy),
+        (Vec3::new(max.x, min.y, min.z), neg(&dx), dy),
+        (Vec3::new(min.x, min.y, min.z), dz, dy),
+        (Vec3::new(min.x, max.y, max.z), dx, neg(&dz)),
+        (Vec3::new(min.x, min.y, min.z), dx, dz),
+    ];
+    for (q, u, v) in sides {
+        world.push(Box::new(Quad::new(&q, &u, &v, material.clone())));
+    }
+}
+
+fn push_random_spheres(world: &mut HittableList, range: i32, rng: &mut impl Rng) {
+    for a in -range..range {
+        for b in -range..range {
+            let mat = rng.gen::<f64>();
+            let center = Vec3::new(
+                a as f64 + 0.9 * rng.gen::<f64>(),
+                0.2,
+                b as f64 + 0.9 * rng.gen::<f64>(),
+            );
+            if Vec3::sub(&center, &Vec3::new(4., 0.2, 0.)).length() > 0.9 {
+                let material: Material = if mat < 0.8 {
+                    let albedo = Vec3::mul_vec(
+                        &Vec3::new_rand_ranged(rng, 0.0, 1.0),
+                        &Vec3::new_rand_ranged(rng, 0.0, 1.0),
+                    );
+                    Material::Lambertian {
+                        albedo: Texture::Solid(albedo),
+                    }
+                } else if mat < 0.95 {
+                    let albedo = Vec3::new_rand_ranged(rng, 0.5, 1.);
+                    let fuzz = 0. + (0.5 * rng.gen::<f64>());
+                    Material::Metal {
+                        albedo: Texture::Solid(albedo),
+                        fuzz,
+                    }
+                } else {
+                    Material::Dielectric {
+                        refraction_index: 1.5,
+                        absorption: Vec3::default(),
+                        dispersion: None,
+                    }
+                };
+                world.push(Box::new(Sphere::new(&center, 0.2, material)));
+            }
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use crate::{
+    rgb_to_spectrum::{self, d65, quadrature, sigmoid_polynomial, TABLE_SIZE},
+    vec3::Vec3,
+};
+use std::sync::OnceLock;
+
+pub use crate::rgb_to_spectrum::{WAVELENGTH_MAX, WAVELENGTH_MIN};
+
+/// A wavelength picked uniformly from the visible range.
+pub fn sample_wavelength(u: f64) -> f64 {
+    WAVELENGTH_MIN + u * (WAVELENGTH_MAX - WAVELENGTH_MIN)
+}
+
+/// The CIE 1931 color matching functions at `wavelength`, using the
+/// multi-lobe Gaussian fit of Wyman, Sloan and Shirley (2013).
+pub fn cie_xyz(wavelength: f64) -> Vec3 {
+    let [x, y, z] = rgb_to_spectrum::cie_xyz(wavelength);
+    Vec3::new(x, y, z)
+}
+
+/// CIE XYZ to linear sRGB, for the D65 white point.
+pub fn xyz_to_linear_srgb(xyz: &Vec3) -> Vec3 {
+    let [r, g, b] = rgb_to_spectrum::xyz_to_linear_srgb(&[xyz.x, xyz.y, xyz.z]);
+    Vec3::new(r, g, b)
+}
+
+/// What a path that carries only `wavelength` contributes to each RGB
+/// channel, scaled so that it averages to white over wavelengths picked
+/// with `sample_wavelength`. Colors outside the sRGB gamut are clipped.
+pub fn rgb_weight(wavelength: f64) -> Vec3 {
+    static NORMALIZATION: OnceLock<Vec3> = OnceLock::new();
+    let unclipped = |wavelength| {
+        let rgb = xyz_to_linear_srgb(&cie_xyz(wavelength));
+        Vec3::new(rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0))
+    };
+    let normalization = NORMALIZATION.get_or_init(|| {
+        let steps = 4000;
+        let mut sum = Vec3::default();
+        for step in 0..steps {
+            sum = Vec3::add(
+                &sum,
+                &unclipped(sample_wavelength((step as f64 + 0.5) / steps as f64)),
+            );
+        }
+        let mean = Vec3::div(&sum, steps as f64);
+        Vec3::new(1.0 / mean.x, 1.0 / mean.y, 1.0 / mean.z)
+    });
+    Vec3::mul_vec(&unclipped(wavelength), normalization)
+}
+
+/// How the index of refraction of a transparent material changes with
+/// wavelength, which is what splits white light into a rainbow.
+#[derive(Clone, Copy, Debug)]
+pub enum Dispersion {
+    /// `n = a + b / λ²`, with λ in micrometers.
+    Cauchy { a: f64, b: f64 },
+    /// `n² = 1 + Σ b·λ² / (λ² - c)`, with λ in micrometers; the usual form
+    /// of glass catalog data.
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    /// Schott N-BK7, the most common optical glass.
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+    pub const FUSED_SILICA: Dispersion = Dispersion::Sellmeier {
+        b: [0.6961663, 0.4079426, 0.8974794],
+        c: [0.0046791483, 0.0135120631, 97.9340025],
+    };
+    /// Schott SF11, a dense flint glass with strong dispersion.
+    pub const DENSE_FLINT: Dispersion = Dispersion::Sellmeier {
+        b: [1.73759695, 0.313747346, 1.89878101],
+        c: [0.013188707, 0.0623068142, 155.23629],
+    };
+    pub const DIAMOND: Dispersion = Dispersion::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030625, 0.011236, 0.0],
+    };
+
+    /// The index of refraction at `wavelength` in nanometers.
+    pub fn refraction_index(&self, wavelength: f64) -> f64 {
+        let micrometers = wavelength / 1000.0;
+        let squared = micrometers * micrometers;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / squared,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f64 = (0..3).map(|i| b[i] * squared / (squared - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
+
+/// Radiance of a black body at `temperature` kelvin by Planck's law, up to
+/// a constant factor.
+fn planck(wavelength: f64, temperature: f64) -> f64 {
+    // second radiation constant hc/k, in meter kelvin
+    const C2: f64 = 1.438776877e-2;
+    let meters = wavelength * 1e-9;
+    1.0 / (meters.powi(5) * ((C2 / (meters * temperature)).exp() - 1.0))
+}
+
+/// Integrates `f` over the visible range.
+fn integrate(f: impl Fn(f64) -> Vec3) -> Vec3 {
+    quadrature().fold(Vec3::default(), |sum, (wavelength, weight)| {
+        Vec3::add(&sum, &Vec3::mul(&f(wavelength), weight))
+    })
+}
+
+/// Linear sRGB of light at just `wavelength`, scaled so that the D65
+/// spectrum integrates to exactly white.
+fn rgb_matching(wavelength: f64) -> Vec3 {
+    let [r, g, b] = rgb_to_spectrum::rgb_matching(wavelength);
+    Vec3::new(r, g, b)
+}
+
+/// The spectrum of a light source, which its RGB color then tints. Scaled
+/// so that an untinted light is about as bright as RGB white.
+#[derive(Clone, Copy, Debug)]
+pub struct Illuminant {
+    /// The black body temperature, or `None` for D65.
+    temperature: Option<f64>,
+    scale: f64,
+    /// What it looks like when rendering in RGB.
+    rgb: Vec3,
+}
+
+impl Illuminant {
+    /// Daylight, which is what white is in sRGB.
+    pub const D65: Illuminant = Illuminant {
+        temperature: None,
+        scale: 1.0,
+        rgb: Vec3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        },
+    };
+
+    /// An incandescent light at `temperature` kelvin: orange around 2000,
+    /// near white at 6500 and blue above.
+    pub fn blackbody(temperature: f64) -> Self {
+        let luminance = |f: &dyn Fn(f64) -> f64| {
+            integrate(|wavelength| Vec3::mul(&cie_xyz(wavelength), f(wavelength))).y
+        };
+        let scale = luminance(&d65) / luminance(&|wavelength| planck(wavelength, temperature));
+        let rgb = integrate(|wavelength| {
+            Vec3::mul(
+                &rgb_matching(wavelength),
+                scale * planck(wavelength, temperature),
+            )
+        });
+        Self {
+            temperature: Some(temperature),
+            scale,
+            // the reddest ones are just outside the sRGB gamut
+            rgb: Vec3::new(rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0)),
+        }
+    }
+
+    pub fn temperature(&self) -> Option<f64> {
+        self.temperature
+    }
+
+    /// Its color when rendering in RGB.
+    pub fn rgb(&self) -> Vec3 {
+        self.rgb
+    }
+
+    /// Its spectral power at `wavelength`.
+    pub fn value(&self, wavelength: f64) -> f64 {
+        match self.temperature {
+            None => d65(wavelength),
+            Some(temperature) => self.scale * planck(wavelength, temperature),
+        }
+    }
+}
+
+/// The wavelengths one path carries in spectral mode: a hero wavelength
+/// picked at random and two more spaced evenly around the visible range
+/// from it (Wilkie et al. 2014). Radiance along such a path is a `Vec3` of
+/// its values at each of them instead of RGB.
+#[derive(Clone, Copy, Debug)]
+pub struct Wavelengths(pub [f64; 3]);
+
+impl Wavelengths {
+    pub fn sample(u: f64) -> Self {
+        Self(std::array::from_fn(|i| {
+            sample_wavelength((u + i as f64 / 3.0).fract())
+        }))
+    }
+
+    /// The wavelength a path follows when the others would take different
+    /// ways, like through dispersive glass.
+    pub fn hero(&self) -> f64 {
+        self.0[0]
+    }
+
+    /// Keeps only the hero wavelength of `value`, weighted to make up for
+    /// the ones dropped.
+    pub fn hero_only(&self, value: &Vec3) -> Vec3 {
+        Vec3::new(3.0 * value.x, 0.0, 0.0)
+    }
+
+    /// A smooth spectrum that reflects `rgb` under white light. Values above
+    /// 1 are scaled down to fit and back up afterwards.
+    pub fn reflectance(&self, rgb: &Vec3) -> Vec3 {
+        let brightest = rgb.x.max(rgb.y).max(rgb.z);
+        self.upsample(
+            rgb,
+            if brightest > 1.0 {
+                2.0 * brightest
+            } else {
+                1.0
+            },
+        )
+    }
+
+    /// Light from `illuminant` tinted by `rgb`.
+    pub fn emission(&self, rgb: &Vec3, illuminant: &Illuminant) -> Vec3 {
+        let brightest = rgb.x.max(rgb.y).max(rgb.z);
+        let tint = self.upsample(rgb, 2.0 * brightest);
+        Vec3::mul_vec(&tint, &self.map(|wavelength| illuminant.value(wavelength)))
+    }
+
+    /// The linear sRGB color of `radiance` at these wavelengths. On average
+    /// over the wavelengths `sample` picks, that is the color of the light.
+    pub fn to_rgb(&self, radiance: &Vec3) -> Vec3 {
+        let values = [radiance.x, radiance.y, radiance.z];
+        let sum = (0..3).fold(Vec3::default(), |sum, i| {
+            Vec3::add(&sum, &Vec3::mul(&rgb_matching(self.0[i]), values[i]))
+        });
+        Vec3::mul(&sum, (WAVELENGTH_MAX - WAVELENGTH_MIN) / 3.0)
+    }
+
+    /// `scale` times the spectrum for `rgb / scale`, which has to be in
+    /// the unit cube.
+    fn upsample(&self, rgb: &Vec3, scale: f64) -> Vec3 {
+        if scale <= 0.0 || rgb.x.max(rgb.y).max(rgb.z) <= 0.0 {
+            return Vec3::default();
+        }
+        let rgb = Vec3::new(rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0));
+        let coefficients = RgbToSpectrum::get().coefficients(&Vec3::div(&rgb, scale));
+        self.map(|wavelength| scale * sigmoid_polynomial(&coefficients, wavelength))
+    }
+
+    fn map(&self, f: impl Fn(f64) -> f64) -> Vec3 {
+        Vec3::new(f(self.0[0]), f(self.0[1]), f(self.0[2]))
+    }
+}
+
+/// Smooth spectra for RGB colors after Jakob and Hanika (2019): a sigmoid
+/// of a quadratic in the wavelength, with coefficients fitted over a grid
+/// of colors at build time and interpolated in between.
+struct RgbToSpectrum {
+    /// The value of the brightest channel at each layer of the grid, closer
+    /// together near 0 and 1 where the fits change fastest.
+    brightness: [f64; TABLE_SIZE],
+    /// By brightest channel, its value, then the next two channels in
+    /// order relative to it.
+    coefficients: Vec<[f64; 3]>,
+}
+
+impl RgbToSpectrum {
+    /// The table `build.rs` fitted, read in on first use.
+    fn get() -> &'static Self {
+        static TABLE: OnceLock<RgbToSpectrum> = OnceLock::new();
+        TABLE.get_or_init(|| {
+            let bytes = include_bytes!(concat!(env!("OUT_DIR"), "/rgb_to_spectrum.bin"));
+            let coefficients = bytes
+                .chunks_exact(24)
+                .map(|entry| {
+                    std::array::from_fn(|i| {
+                        f64::from_le_bytes(entry[8 * i..8 * i + 8].try_into().unwrap())
+                    })
+                })
+                .collect();
+            Self {
+                brightness: rgb_to_spectrum::brightness(),
+                coefficients,
+            }
+        })
+    }
+
+    /// Coefficients of the spectrum for `rgb`, which has to be in the unit
+    /// cube.
+    fn coefficients(&self, rgb: &Vec3) -> [f64; 3] {
+        if rgb.x == rgb.y && rgb.y == rgb.z {
+            // constant, and infinite for black and white
+            return [0.0, 0.0, (rgb.x - 0.5) / (rgb.x * (1.0 - rgb.x)).sqrt()];
+        }
+        let n = TABLE_SIZE;
+        let rgb = [rgb.x, rgb.y, rgb.z];
+        let channel = (0..3)
+            .max_by(|&a, &b| rgb[a].total_cmp(&rgb[b]))
+            .unwrap_or(0);
+        let z = rgb[channel].min(1.0);
+        let x = rgb[(channel + 1) % 3] / z * (n - 1) as f64;
+        let y = rgb[(channel + 2) % 3] / z * (n - 1) as f64;
+        let (xi, yi) = ((x as usize).min(n - 2), (y as usize).min(n - 2));
+        let zi = self
+            .brightness
+            .partition_point(|&brightness| brightness < z)
+            .clamp(1, n - 1)
+            - 1;
+        let (dx, dy) = (x - xi as f64, y - yi as f64);
+        let dz = (z - self.brightness[zi]) / (self.brightness[zi + 1] - self.brightness[zi]);
+
+        let mut result = [0.0; 3];
+        for (k, wz) in [(zi, 1.0 - dz), (zi + 1, dz)] {
+            for (j, wy) in [(yi, 1.0 - dy), (yi + 1, dy)] {
+                for (i, wx) in [(xi, 1.0 - dx), (xi + 1, dx)] {
+                    let value = &self.coefficients[((channel * n + k) * n + j) * n + i];
+                    for (result, value) in result.iter_mut().zip(value) {
+                        *result += wz * wy * wx * value;
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spectra_reflect_the_colors_they_were_made_for() {
+        // a grid off the table's own points, so interpolation is tested too
+        let steps = 9;
+        let mut worst = 0.0_f64;
+        for r in 0..=steps {
+            for g in 0..=steps {
+                for b in 0..=steps {
+                    let rgb = Vec3::new(r as f64, g as f64, b as f64);
+                    let rgb = Vec3::div(&rgb, steps as f64 + 0.3);
+                    let coefficients = RgbToSpectrum::get().coefficients(&rgb);
+                    let reflected = integrate(|wavelength| {
+                        Vec3::mul(
+                            &rgb_matching(wavelength),
+                            d65(wavelength) * sigmoid_polynomial(&coefficients, wavelength),
+                        )
+                    });
+                    let error = Vec3::sub(&reflected, &rgb);
+                    worst = worst.max(error.x.abs().max(error.y.abs()).max(error.z.abs()));
+                }
+            }
+        }
+        assert!(worst < 0.005, "off by up to {worst}");
+    }
+
+    #[test]
+    fn glasses_match_their_catalog_indices() {
+        // Fraunhofer d, F and C lines
+        let (d, f, c) = (587.56, 486.13, 656.27);
+        for (name, glass, n_d, abbe) in [
+            ("BK7", Dispersion::BK7, 1.5168, 64.17),
+            ("fused silica", Dispersion::FUSED_SILICA, 1.4585, 67.82),
+            ("SF11", Dispersion::DENSE_FLINT, 1.78472, 25.68),
+            ("diamond", Dispersion::DIAMOND, 2.4175, 55.3),
+        ] {
+            let index = glass.refraction_index(d);
+            assert!((index - n_d).abs() < 2e-4, "{name}: n_d is {index}");
+            let number = (index - 1.0) / (glass.refraction_index(f) - glass.refraction_index(c));
+            assert!(
+                (number - abbe).abs() < 0.5,
+                "{name}: Abbe number is {number}"
+            );
+        }
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.004 };
+        assert!((cauchy.refraction_index(400.0) - 1.525).abs() < 1e-12);
+        assert!(cauchy.refraction_index(400.0) > cauchy.refraction_index(700.0));
+    }
+
+    #[test]
+    fn single_wavelengths_average_to_white() {
+        let steps = 1000;
+        let mut sum = Vec3::default();
+        for step in 0..steps {
+            let wavelength = sample_wavelength((step as f64 + 0.5) / steps as f64);
+            sum = Vec3::add(&sum, &rgb_weight(wavelength));
+        }
+        let mean = Vec3::div(&sum, steps as f64);
+        for channel in [mean.x, mean.y, mean.z] {
+            assert!((channel - 1.0).abs() < 1e-3, "{mean:?}");
+        }
+    }
+}
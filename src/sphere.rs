@@ -1,9 +1,11 @@
 use crate::{
+    aabb::Aabb,
     hit_record::{HitRecord, Hittable},
     interval::Interval,
     material::Material,
     vec3::Vec3,
 };
+use std::f64::consts::PI;
 
 #[derive(Clone)]
 pub struct Sphere {
@@ -20,9 +22,24 @@ impl Sphere {
         }
     }
 }
+impl Sphere {
+    /// Maps a point on the unit sphere to `u` (angle around the y axis,
+    /// starting at -x) and `v` (angle from -y up to +y), both in `[0, 1]`.
+    pub fn uv(point: &Vec3) -> (f64, f64) {
+        let theta = (-point.y).clamp(-1.0, 1.0).acos();
+        let phi = (-point.z).atan2(point.x) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+}
+
 //todo remove mut?
 impl Hittable for Sphere {
-    fn hit(&self, ray: &crate::ray::Ray, interval: &Interval, hit_record: &mut HitRecord) -> bool {
+    fn hit<'a>(
+        &'a self,
+        ray: &crate::ray::Ray,
+        interval: &Interval,
+        hit_record: &mut HitRecord<'a>,
+    ) -> bool {
         let oc = Vec3::sub(&ray.origin, &self.center);
         let a = ray.direction.length_squared();
         let h = Vec3::dot(&oc, &ray.direction);
@@ -46,7 +63,16 @@ impl Hittable for Sphere {
         let outward_normal = Vec3::div(&Vec3::sub(&hit_record.point, &self.center), self.radius);
         // hit_record.normal = Vec3::div(&Vec3::sub(&hit_record.point, &self.center ), self.radius);
         hit_record.set_face_normal(ray, &outward_normal);
-        hit_record.material = Some(self.material);
+        (hit_record.u, hit_record.v) = Self::uv(&outward_normal);
+        hit_record.material = Some(&self.material);
         true
     }
+
+    fn bounding_box(&self) -> Aabb {
+        let rvec = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::new_from_points(
+            &Vec3::sub(&self.center, &rvec),
+            &Vec3::add(&self.center, &rvec),
+        )
+    }
 }
@@ -0,0 +1,223 @@
+use crate::vec3::Vec3;
+use rand::Rng;
+use std::{path::Path, sync::Arc};
+
+#[derive(Clone, Copy, Debug)]
+pub enum NoiseStyle {
+    /// Plain Perlin noise.
+    Smooth,
+    /// Sum of several octaves of noise.
+    Turbulence,
+    /// Sine stripes along z, disturbed by turbulence.
+    Marble,
+}
+
+/// A color that varies over a surface, looked up by texture coordinates and
+/// hit point.
+#[derive(Clone)]
+pub enum Texture {
+    Solid(Vec3),
+    /// Alternating cubes of side `scale` in world space.
+    Checker {
+        scale: f64,
+        even: Arc<Texture>,
+        odd: Arc<Texture>,
+    },
+    Image(Arc<ImageTexture>),
+    Noise {
+        perlin: Arc<Perlin>,
+        scale: f64,
+        style: NoiseStyle,
+    },
+}
+
+impl Texture {
+    pub fn checker(scale: f64, even: Texture, odd: Texture) -> Self {
+        Texture::Checker {
+            scale,
+            even: Arc::new(even),
+            odd: Arc::new(odd),
+        }
+    }
+    pub fn noise(scale: f64, style: NoiseStyle, rng: &mut impl Rng) -> Self {
+        Texture::Noise {
+            perlin: Arc::new(Perlin::new(rng)),
+            scale,
+            style,
+        }
+    }
+
+    pub fn value(&self, u: f64, v: f64, point: &Vec3) -> Vec3 {
+        match self {
+            Texture::Solid(color) => *color,
+            Texture::Checker { scale, even, odd } => {
+                let x = (point.x / scale).floor() as i64;
+                let y = (point.y / scale).floor() as i64;
+                let z = (point.z / scale).floor() as i64;
+                if (x + y + z) % 2 == 0 {
+                    even.value(u, v, point)
+                } else {
+                    odd.value(u, v, point)
+                }
+            }
+            Texture::Image(image) => image.value(u, v),
+            Texture::Noise {
+                perlin,
+                scale,
+                style,
+            } => {
+                let scaled = Vec3::mul(point, *scale);
+                let gray = match style {
+                    NoiseStyle::Smooth => 0.5 * (1.0 + perlin.noise(&scaled)),
+                    NoiseStyle::Turbulence => perlin.turbulence(&scaled, 7),
+                    NoiseStyle::Marble => {
+                        0.5 * (1.0 + (scaled.z + 10.0 * perlin.turbulence(point, 7)).sin())
+                    }
+                };
+                Vec3::new(gray, gray, gray)
+            }
+        }
+    }
+}
+
+/// An 8-bit image converted to linear RGB when loaded.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+}
+
+impl ImageTexture {
+    /// Loads a PNG or JPEG file, assuming sRGB-encoded pixels.
+    pub fn load(path: &Path) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.to_rgb8();
+        let (width, height) = image.dimensions();
+        let pixels = image
+            .pixels()
+            .map(|p| {
+                Vec3::new(
+                    srgb_to_linear(p[0]),
+                    srgb_to_linear(p[1]),
+                    srgb_to_linear(p[2]),
+                )
+            })
+            .collect();
+        Ok(Self {
+            width: width as usize,
+            height: height as usize,
+            pixels,
+        })
+    }
+
+    /// Nearest-pixel lookup. Coordinates wrap around, and `v` runs from the
+    /// bottom of the image to the top.
+    pub fn value(&self, u: f64, v: f64) -> Vec3 {
+        if self.pixels.is_empty() {
+            return Vec3::new(0.0, 1.0, 1.0);
+        }
+        let u = u - u.floor();
+        let v = 1.0 - (v - v.floor());
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[j * self.width + i]
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let c = value as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+const POINT_COUNT: usize = 256;
+
+/// Ken Perlin's gradient noise over random unit vectors.
+pub struct Perlin {
+    random_vectors: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(rng: &mut impl Rng) -> Self {
+        let random_vectors = (0..POINT_COUNT)
+            .map(|_| Vec3::unit(&Vec3::new_rand_ranged(rng, -1.0, 1.0)))
+            .collect();
+        Self {
+            random_vectors,
+            perm_x: Self::generate_perm(rng),
+            perm_y: Self::generate_perm(rng),
+            perm_z: Self::generate_perm(rng),
+        }
+    }
+
+    fn generate_perm(rng: &mut impl Rng) -> Vec<usize> {
+        let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+        for i in (1..POINT_COUNT).rev() {
+            let target = rng.gen_range(0..=i);
+            perm.swap(i, target);
+        }
+        perm
+    }
+
+    /// Noise in `[-1, 1]`.
+    pub fn noise(&self, point: &Vec3) -> f64 {
+        let u = point.x - point.x.floor();
+        let v = point.y - point.y.floor();
+        let w = point.z - point.z.floor();
+        let i = point.x.floor() as i64;
+        let j = point.y.floor() as i64;
+        let k = point.z.floor() as i64;
+
+        let mut c = [[[Vec3::default(); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    let index = self.perm_x[((i + di as i64) & 255) as usize]
+                        ^ self.perm_y[((j + dj as i64) & 255) as usize]
+                        ^ self.perm_z[((k + dk as i64) & 255) as usize];
+                    *corner = self.random_vectors[index];
+                }
+            }
+        }
+        Self::trilinear_interp(&c, u, v, w)
+    }
+
+    pub fn turbulence(&self, point: &Vec3, depth: usize) -> f64 {
+        let mut accum = 0.0;
+        let mut temp = *point;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp);
+            weight *= 0.5;
+            temp = Vec3::mul(&temp, 2.0);
+        }
+        accum.abs()
+    }
+
+    fn trilinear_interp(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+        // Hermite smoothing hides the grid
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+
+        let mut accum = 0.0;
+        for (i, plane) in c.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, corner) in row.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * Vec3::dot(corner, &weight);
+                }
+            }
+        }
+        accum
+    }
+}
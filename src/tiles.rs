@@ -0,0 +1,242 @@
+use crate::framebuffer::{Framebuffer, PixelStats};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// The order tiles are handed out to the render threads in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TileOrder {
+    /// Outwards from the center of the image, where the subject usually is.
+    #[default]
+    Spiral,
+    /// Along a Hilbert curve, which keeps consecutive tiles next to each
+    /// other for better cache use.
+    Hilbert,
+    /// Row by row from the top.
+    Scanline,
+}
+
+/// A rectangle of pixels rendered as one unit of work.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Sent after every finished tile.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub tile: Tile,
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        self.tiles_done as f64 / self.tiles_total.max(1) as f64
+    }
+}
+
+/// Ways to follow and steer a render while it runs.
+pub struct RenderHooks<'a> {
+    /// Called from the render threads after each tile.
+    pub progress: &'a (dyn Fn(&Progress) + Sync),
+    /// Once set, the render stops after the pixels in progress. Pixels that
+    /// weren't rendered keep the samples they had.
+    pub cancel: &'a AtomicBool,
+    /// Called with the framebuffer every `checkpoint_interval`, with only
+    /// whole pixels updated, so it can be saved as a checkpoint.
+    pub checkpoint: Option<&'a (dyn Fn(&Framebuffer) + Sync)>,
+    pub checkpoint_interval: Duration,
+}
+
+static NEVER_CANCELLED: AtomicBool = AtomicBool::new(false);
+
+fn ignore_progress(_: &Progress) {}
+
+impl Default for RenderHooks<'_> {
+    /// No progress reports, cancellation or checkpoints.
+    fn default() -> Self {
+        Self {
+            progress: &ignore_progress,
+            cancel: &NEVER_CANCELLED,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Splits an image into tiles and renders them on the rayon thread pool.
+#[derive(Clone, Copy, Debug)]
+pub struct TileScheduler {
+    /// Width and height of a tile in pixels; tiles on the right and bottom
+    /// edges may be smaller.
+    pub tile_size: usize,
+    pub order: TileOrder,
+}
+
+impl Default for TileScheduler {
+    fn default() -> Self {
+        Self {
+            tile_size: 32,
+            order: TileOrder::Spiral,
+        }
+    }
+}
+
+impl TileScheduler {
+    pub fn new(tile_size: usize, order: TileOrder) -> Self {
+        Self { tile_size, order }
+    }
+
+    /// The tiles covering a `width` x `height` image, in render order.
+    pub fn tiles(&self, width: usize, height: usize) -> Vec<Tile> {
+        let size = self.tile_size.max(1);
+        let (columns, rows) = (width.div_ceil(size), height.div_ceil(size));
+        let mut cells: Vec<(usize, usize)> = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .collect();
+        match self.order {
+            TileOrder::Scanline => {}
+            TileOrder::Spiral => {
+                // rings of growing distance from the center, each walked
+                // around by angle
+                let center_x = (columns as f64 - 1.0) / 2.0;
+                let center_y = (rows as f64 - 1.0) / 2.0;
+                let key = |&(column, row): &(usize, usize)| {
+                    let dx = column as f64 - center_x;
+                    let dy = row as f64 - center_y;
+                    (dx.abs().max(dy.abs()), dy.atan2(dx))
+                };
+                cells.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+            }
+            TileOrder::Hilbert => {
+                let side = columns.max(rows).next_power_of_two();
+                cells.sort_by_key(|&(column, row)| hilbert_index(side, column, row));
+            }
+        }
+        cells
+            .into_iter()
+            .map(|(column, row)| {
+                let (x, y) = (column * size, row * size);
+                Tile {
+                    x,
+                    y,
+                    width: size.min(width - x),
+                    height: size.min(height - y),
+                }
+            })
+            .collect()
+    }
+
+    /// Calls `render_pixel` on every pixel of `framebuffer` to add samples
+    /// to it, tile by tile in parallel, and returns it once all tiles are
+    /// done or the render is cancelled.
+    pub fn run<F>(
+        &self,
+        framebuffer: Framebuffer,
+        hooks: &RenderHooks,
+        render_pixel: F,
+    ) -> Framebuffer
+    where
+        F: Fn(usize, usize, &mut PixelStats) + Sync,
+    {
+        let tiles = self.tiles(framebuffer.width, framebuffer.height);
+        let framebuffer = Mutex::new(framebuffer);
+        let next_tile = AtomicUsize::new(0);
+        let tiles_done = AtomicUsize::new(0);
+        let start = Instant::now();
+        let last_checkpoint = Mutex::new(start);
+
+        // every thread keeps taking the next tile in order, so they are
+        // finished roughly in that order as well
+        rayon::broadcast(|_| loop {
+            if hooks.cancel.load(Ordering::Relaxed) {
+                return;
+            }
+            let Some(&tile) = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) else {
+                return;
+            };
+            let pixels = || {
+                (tile.y..tile.y + tile.height)
+                    .flat_map(move |j| (tile.x..tile.x + tile.width).map(move |i| (i, j)))
+            };
+            let mut stats: Vec<PixelStats> = {
+                let framebuffer = framebuffer.lock().unwrap();
+                pixels().map(|(i, j)| *framebuffer.get(i, j)).collect()
+            };
+            // checked per pixel as well, so a tile with many samples per
+            // pixel doesn't hold up cancelling
+            let mut finished = true;
+            for ((i, j), stats) in pixels().zip(stats.iter_mut()) {
+                if hooks.cancel.load(Ordering::Relaxed) {
+                    finished = false;
+                    break;
+                }
+                render_pixel(i, j, stats);
+            }
+            let mut due_checkpoint = None;
+            {
+                let mut framebuffer = framebuffer.lock().unwrap();
+                for ((i, j), stats) in pixels().zip(stats) {
+                    let index = framebuffer.index(i, j);
+                    framebuffer.pixels[index] = stats;
+                }
+                // whoever holds `last_checkpoint` is saving one, the other
+                // threads go on rendering
+                if let Some(checkpoint) = hooks.checkpoint {
+                    if let Ok(last_checkpoint) = last_checkpoint.try_lock() {
+                        if last_checkpoint.elapsed() >= hooks.checkpoint_interval {
+                            due_checkpoint =
+                                Some((checkpoint, last_checkpoint, framebuffer.clone()));
+                        }
+                    }
+                }
+            }
+            // saved from a copy so the render threads don't wait on the disk
+            if let Some((checkpoint, mut last_checkpoint, framebuffer)) = due_checkpoint {
+                checkpoint(&framebuffer);
+                *last_checkpoint = Instant::now();
+            }
+            if !finished {
+                return;
+            }
+            (hooks.progress)(&Progress {
+                tile,
+                tiles_done: tiles_done.fetch_add(1, Ordering::Relaxed) + 1,
+                tiles_total: tiles.len(),
+                elapsed: start.elapsed(),
+            });
+        });
+        framebuffer.into_inner().unwrap()
+    }
+}
+
+/// Position of cell `(x, y)` along the Hilbert curve filling a `side` x
+/// `side` grid, `side` being a power of two.
+fn hilbert_index(side: usize, mut x: usize, mut y: usize) -> usize {
+    let mut index = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = usize::from(x & s > 0);
+        let ry = usize::from(y & s > 0);
+        index += s * s * ((3 * rx) ^ ry);
+        // rotate the quadrant so the curve inside it lines up
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
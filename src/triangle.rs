@@ -0,0 +1,145 @@
+use crate::{
+    aabb::Aabb,
+    hit_record::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    vec3::Vec3,
+};
+
+#[derive(Clone)]
+pub struct Triangle {
+    pub vertices: [Vec3; 3],
+    pub normals: Option<[Vec3; 3]>,
+    pub uvs: Option<[(f64, f64); 3]>,
+    pub material: Material,
+}
+impl Triangle {
+    pub fn new(a: &Vec3, b: &Vec3, c: &Vec3, material: Material) -> Self {
+        Self {
+            vertices: [*a, *b, *c],
+            normals: None,
+            uvs: None,
+            material,
+        }
+    }
+    /// Per-vertex normals, interpolated across the face for smooth shading.
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Self {
+        self.normals = Some(normals);
+        self
+    }
+    pub fn with_uvs(mut self, uvs: [(f64, f64); 3]) -> Self {
+        self.uvs = Some(uvs);
+        self
+    }
+
+    /// Möller–Trumbore ray/triangle test. Returns `t` and the barycentric
+    /// weights of the second and third vertex.
+    pub fn intersect(
+        ray: &Ray,
+        vertices: &[Vec3; 3],
+        interval: &Interval,
+    ) -> Option<(f64, f64, f64)> {
+        const EPS: f64 = 1e-12;
+        let edge1 = Vec3::sub(&vertices[1], &vertices[0]);
+        let edge2 = Vec3::sub(&vertices[2], &vertices[0]);
+        let pvec = Vec3::cross(&ray.direction, &edge2);
+        let det = Vec3::dot(&edge1, &pvec);
+        if det.abs() < EPS {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = Vec3::sub(&ray.origin, &vertices[0]);
+        let b1 = Vec3::dot(&tvec, &pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = Vec3::cross(&tvec, &edge1);
+        let b2 = Vec3::dot(&ray.direction, &qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = Vec3::dot(&edge2, &qvec) * inv_det;
+        if !interval.surrounds(t) {
+            return None;
+        }
+        Some((t, b1, b2))
+    }
+
+    /// Fills `hit_record` for a hit found by `intersect`, shared by lone
+    /// triangles and mesh faces.
+    pub fn fill_hit_record<'a>(
+        ray: &Ray,
+        vertices: &[Vec3; 3],
+        normals: Option<[Vec3; 3]>,
+        uvs: Option<[(f64, f64); 3]>,
+        material: &'a Material,
+        (t, b1, b2): (f64, f64, f64),
+        hit_record: &mut HitRecord<'a>,
+    ) {
+        let b0 = 1.0 - b1 - b2;
+        hit_record.t = t;
+        hit_record.point = ray.at(t);
+        hit_record.barycentric = Vec3::new(b0, b1, b2);
+
+        let geometric_normal = Vec3::unit(&Vec3::cross(
+            &Vec3::sub(&vertices[1], &vertices[0]),
+            &Vec3::sub(&vertices[2], &vertices[0]),
+        ));
+        hit_record.set_face_normal(ray, &geometric_normal);
+
+        if let Some(n) = normals {
+            let mut shading_normal = Vec3::add(
+                &Vec3::mul(&n[0], b0),
+                &Vec3::add(&Vec3::mul(&n[1], b1), &Vec3::mul(&n[2], b2)),
+            );
+            if !shading_normal.near_zero() {
+                // keep the shading normal on the same side as the face the ray hit
+                if Vec3::dot(&shading_normal, &hit_record.normal) < 0.0 {
+                    shading_normal = Vec3::mul(&shading_normal, -1.0);
+                }
+                hit_record.normal = Vec3::unit(&shading_normal);
+            }
+        }
+
+        (hit_record.u, hit_record.v) = match uvs {
+            Some(uv) => (
+                b0 * uv[0].0 + b1 * uv[1].0 + b2 * uv[2].0,
+                b0 * uv[0].1 + b1 * uv[1].1 + b2 * uv[2].1,
+            ),
+            None => (b1, b2),
+        };
+        hit_record.material = Some(material);
+    }
+
+    pub fn bounds(vertices: &[Vec3; 3]) -> Aabb {
+        Aabb::union(
+            &Aabb::new_from_points(&vertices[0], &vertices[1]),
+            &Aabb::new_from_points(&vertices[2], &vertices[2]),
+        )
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit<'a>(&'a self, ray: &Ray, interval: &Interval, hit_record: &mut HitRecord<'a>) -> bool {
+        match Self::intersect(ray, &self.vertices, interval) {
+            Some(hit) => {
+                Self::fill_hit_record(
+                    ray,
+                    &self.vertices,
+                    self.normals,
+                    self.uvs,
+                    &self.material,
+                    hit,
+                    hit_record,
+                );
+                true
+            }
+            None => false,
+        }
+    }
+    fn bounding_box(&self) -> Aabb {
+        Self::bounds(&self.vertices)
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{
+    aabb::Aabb,
+    bvh::BvhNode,
+    hit_record::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    triangle::Triangle,
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+/// Vertex buffers shared by every face of a mesh. `normals` and `uvs` are
+/// either empty or the same length as `positions`.
+pub struct MeshData {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub indices: Vec<[usize; 3]>,
+    pub material: Material,
+}
+impl MeshData {
+    fn vertices(&self, face: usize) -> [Vec3; 3] {
+        let [a, b, c] = self.indices[face];
+        [self.positions[a], self.positions[b], self.positions[c]]
+    }
+    fn normals(&self, face: usize) -> Option<[Vec3; 3]> {
+        if self.normals.is_empty() {
+            return None;
+        }
+        let [a, b, c] = self.indices[face];
+        Some([self.normals[a], self.normals[b], self.normals[c]])
+    }
+    fn uvs(&self, face: usize) -> Option<[(f64, f64); 3]> {
+        if self.uvs.is_empty() {
+            return None;
+        }
+        let [a, b, c] = self.indices[face];
+        Some([self.uvs[a], self.uvs[b], self.uvs[c]])
+    }
+}
+
+/// One face of a `TriangleMesh`, looked up through the shared buffers.
+struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    face: usize,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit<'a>(&'a self, ray: &Ray, interval: &Interval, hit_record: &mut HitRecord<'a>) -> bool {
+        let vertices = self.mesh.vertices(self.face);
+        match Triangle::intersect(ray, &vertices, interval) {
+            Some(hit) => {
+                Triangle::fill_hit_record(
+                    ray,
+                    &vertices,
+                    self.mesh.normals(self.face),
+                    self.mesh.uvs(self.face),
+                    &self.mesh.material,
+                    hit,
+                    hit_record,
+                );
+                true
+            }
+            None => false,
+        }
+    }
+    fn bounding_box(&self) -> Aabb {
+        Triangle::bounds(&self.mesh.vertices(self.face))
+    }
+}
+
+/// Indexed triangle mesh with its own BVH over the faces.
+pub struct TriangleMesh {
+    pub data: Arc<MeshData>,
+    root: Box<dyn Hittable>,
+}
+impl TriangleMesh {
+    /// Fails unless every index is in range of `positions`, and `normals`
+    /// and `uvs` are each empty or as long as `positions`.
+    pub fn new(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f64, f64)>,
+        indices: Vec<[usize; 3]>,
+        material: Material,
+    ) -> Result<Self, String> {
+        let count = positions.len();
+        if !normals.is_empty() && normals.len() != count {
+            return Err(format!("{} normals for {} positions", normals.len(), count));
+        }
+        if !uvs.is_empty() && uvs.len() != count {
+            return Err(format!("{} uvs for {} positions", uvs.len(), count));
+        }
+        if let Some(face) = indices
+            .iter()
+            .position(|face| face.iter().any(|&i| i >= count))
+        {
+            return Err(format!(
+                "face {} has an index out of range ({} positions)",
+                face, count
+            ));
+        }
+        let data = Arc::new(MeshData {
+            positions,
+            normals,
+            uvs,
+            indices,
+            material,
+        });
+        let faces = (0..data.indices.len())
+            .map(|face| {
+                Box::new(MeshTriangle {
+                    mesh: Arc::clone(&data),
+                    face,
+                }) as Box<dyn Hittable>
+            })
+            .collect();
+        Ok(Self {
+            data,
+            root: BvhNode::build(faces),
+        })
+    }
+    pub fn triangle_count(&self) -> usize {
+        self.data.indices.len()
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit<'a>(&'a self, ray: &Ray, interval: &Interval, hit_record: &mut HitRecord<'a>) -> bool {
+        self.root.hit(ray, interval, hit_record)
+    }
+    fn bounding_box(&self) -> Aabb {
+        self.root.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::DEFAULT_MATERIAL;
+
+    fn triangle() -> Vec<Vec3> {
+        vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ]
+    }
+
+    #[test]
+    fn rejects_indices_out_of_range() {
+        let mesh = TriangleMesh::new(
+            triangle(),
+            vec![],
+            vec![],
+            vec![[0, 1, 3]],
+            DEFAULT_MATERIAL,
+        );
+        assert_eq!(
+            mesh.err().unwrap(),
+            "face 0 has an index out of range (3 positions)"
+        );
+    }
+
+    #[test]
+    fn rejects_normals_that_dont_match_the_positions() {
+        let normals = vec![Vec3::new(0.0, 0.0, 1.0)];
+        let mesh = TriangleMesh::new(
+            triangle(),
+            normals,
+            vec![],
+            vec![[0, 1, 2]],
+            DEFAULT_MATERIAL,
+        );
+        assert!(mesh.is_err());
+        let mesh = TriangleMesh::new(
+            triangle(),
+            vec![],
+            vec![],
+            vec![[0, 1, 2]],
+            DEFAULT_MATERIAL,
+        );
+        assert_eq!(mesh.unwrap().triangle_count(), 1);
+    }
+}
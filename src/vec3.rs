@@ -20,16 +20,14 @@ impl Vec3 {
     pub fn new(x: f64, y: f64, z: f64) -> Vec3 {
         Vec3 { x, y, z }
     }
-    pub fn new_rand() -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn new_rand(rng: &mut impl Rng) -> Self {
         Vec3 {
             x: rng.gen(),
             y: rng.gen(),
             z: rng.gen(),
         }
     }
-    pub fn new_rand_ranged(min: f64, max: f64) -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn new_rand_ranged(rng: &mut impl Rng, min: f64, max: f64) -> Self {
         Vec3 {
             x: min + (max - min) * rng.gen::<f64>(),
             y: min + (max - min) * rng.gen::<f64>(),
@@ -37,9 +35,9 @@ impl Vec3 {
         }
     }
 
-    pub fn new_rand_unit() -> Vec3 {
+    pub fn new_rand_unit(rng: &mut impl Rng) -> Vec3 {
         loop {
-            let tmp = Self::new_rand_ranged(-1.0, 1.0);
+            let tmp = Self::new_rand_ranged(rng, -1.0, 1.0);
             let lensq = tmp.length_squared();
             if 1e-160 < lensq && lensq <= 1.0 {
                 return Vec3::div(&tmp, lensq.sqrt());
@@ -47,8 +45,8 @@ impl Vec3 {
         }
     }
 
-    pub fn rand_on_hemisphere(normal: &Vec3) -> Vec3 {
-        let r = Self::new_rand_unit();
+    pub fn rand_on_hemisphere(normal: &Vec3, rng: &mut impl Rng) -> Vec3 {
+        let r = Self::new_rand_unit(rng);
         if Self::dot(&r, normal) > 0.0 {
             r
         } else {
@@ -56,6 +54,27 @@ impl Vec3 {
         }
     }
 
+    /// Two unit vectors completing unit vector `w` to an orthonormal basis,
+    /// returned as `(u, v, w)`.
+    pub fn orthonormal_basis(w: &Vec3) -> (Vec3, Vec3, Vec3) {
+        let a = if w.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = Vec3::unit(&Vec3::cross(w, &a));
+        let u = Vec3::cross(w, &v);
+        (u, v, *w)
+    }
+
+    pub fn axis(&self, n: usize) -> f64 {
+        match n {
+            1 => self.y,
+            2 => self.z,
+            _ => self.x,
+        }
+    }
+
     pub fn length_squared(&self) -> f64 {
         (self.x * self.x) + (self.y * self.y) + (self.z * self.z)
     }
@@ -107,7 +126,7 @@ impl Vec3 {
         Vec3 {
             x: lhs.y * rhs.z - lhs.z * rhs.y,
             y: lhs.z * rhs.x - lhs.x * rhs.z,
-            z: lhs.z * rhs.y - lhs.y * rhs.x,
+            z: lhs.x * rhs.y - lhs.y * rhs.x,
         }
     }
 
@@ -133,8 +152,7 @@ impl Vec3 {
         );
         Vec3::add(&r_out, &r_out_parallel)
     }
-    pub fn random_in_unit_disk() -> Vec3 {
-        let mut rng = rand::thread_rng();
+    pub fn random_in_unit_disk(rng: &mut impl Rng) -> Vec3 {
         loop {
             let p = Vec3 {
                 x: -1. + (1. - -1.) * rng.gen::<f64>(),
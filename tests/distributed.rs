@@ -0,0 +1,115 @@
+use raytracer_rust::{
+    distributed::{self, Job},
+    render,
+    scene::LoadOptions,
+    tiles::{TileOrder, TileScheduler},
+    Framebuffer, Image, Scene,
+};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+fn job() -> Job {
+    let scene_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("scenes")
+        .join("cornell_box.toml");
+    Job {
+        source: fs::read_to_string(&scene_path).unwrap(),
+        scene_path,
+        options: LoadOptions {
+            width: Some(24),
+            height: Some(16),
+            samples_per_pixel: Some(4),
+            max_depth: Some(8),
+            seed: Some(3),
+            ..Default::default()
+        },
+        timeout: Duration::from_secs(30),
+    }
+}
+
+/// Starts a coordinator for `job` on a free loopback port, returning its
+/// address and where its image arrives.
+fn start_coordinator(job: Job) -> (String, mpsc::Receiver<Image>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let scene = Scene::parse(&job.source, &job.scene_path, &job.options).unwrap();
+        let (width, height) = (scene.camera.image_width, scene.camera.image_height);
+        let tiles = TileScheduler::new(8, TileOrder::Scanline).tiles(width, height);
+        let framebuffer = distributed::coordinate(
+            listener,
+            &job,
+            tiles,
+            Framebuffer::new(width, height),
+            &|_| {},
+        )
+        .unwrap();
+        sender.send(framebuffer.to_image()).unwrap();
+    });
+    (address, receiver)
+}
+
+fn start_worker(address: &str) {
+    let address = address.to_string();
+    thread::spawn(move || distributed::work(&address).map_err(|err| err.to_string()));
+}
+
+fn local_render(job: &Job) -> Image {
+    let scene = Scene::parse(&job.source, &job.scene_path, &job.options).unwrap();
+    render(&scene, &scene.settings)
+}
+
+fn assert_same(a: &Image, b: &Image) {
+    assert_eq!((a.width, a.height), (b.width, b.height));
+    for (index, (a, b)) in a.pixels.iter().zip(&b.pixels).enumerate() {
+        assert!(
+            a.x.to_bits() == b.x.to_bits()
+                && a.y.to_bits() == b.y.to_bits()
+                && a.z.to_bits() == b.z.to_bits(),
+            "pixel {index} differs: {a:?} and {b:?}"
+        );
+    }
+}
+
+const WAIT: Duration = Duration::from_secs(60);
+
+#[test]
+fn workers_render_the_same_image_as_one_process() {
+    let (address, image) = start_coordinator(job());
+    start_worker(&address);
+    start_worker(&address);
+    let image = image.recv_timeout(WAIT).expect("the render didn't finish");
+    assert_same(&image, &local_render(&job()));
+}
+
+#[test]
+fn tile_of_a_worker_that_drops_out_is_rendered_by_another() {
+    let (address, image) = start_coordinator(job());
+
+    // takes a tile and disconnects without sending it back
+    let stream = TcpStream::connect(&address).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert!(line.contains("\"type\":\"scene\""));
+    writer.write_all(b"{\"type\":\"ready\"}\n").unwrap();
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert!(line.contains("\"type\":\"tile\""));
+    drop((reader, writer));
+
+    start_worker(&address);
+    let image = image
+        .recv_timeout(WAIT)
+        .expect("the dropped tile was never rendered");
+    assert_same(&image, &local_render(&job()));
+}
@@ -0,0 +1,67 @@
+use raytracer_rust::{render, scene::LoadOptions, tiles::TileScheduler, Image, Scene};
+use std::path::Path;
+
+/// `name` from the scenes folder, made small enough to render quickly.
+fn load(name: &str) -> Scene {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("scenes")
+        .join(name);
+    let options = LoadOptions {
+        width: Some(24),
+        height: Some(24),
+        samples_per_pixel: Some(4),
+        max_depth: Some(8),
+        seed: Some(7),
+        ..Default::default()
+    };
+    Scene::load_with(&path, &options).unwrap()
+}
+
+/// Renders `scene` on a pool of `threads` threads, in small tiles so the
+/// threads take turns.
+fn render_on(scene: &Scene, threads: usize) -> Image {
+    let mut settings = scene.settings;
+    settings.tiles = TileScheduler {
+        tile_size: 4,
+        ..settings.tiles
+    };
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap()
+        .install(|| render(scene, &settings))
+}
+
+fn assert_same(a: &Image, b: &Image) {
+    assert_eq!((a.width, a.height), (b.width, b.height));
+    for (index, (a, b)) in a.pixels.iter().zip(&b.pixels).enumerate() {
+        assert!(
+            a.x.to_bits() == b.x.to_bits()
+                && a.y.to_bits() == b.y.to_bits()
+                && a.z.to_bits() == b.z.to_bits(),
+            "pixel {index} differs: {a:?} and {b:?}"
+        );
+    }
+}
+
+#[test]
+fn same_image_for_any_number_of_threads() {
+    for name in ["cornell_box.toml", "textures.toml"] {
+        let scene = load(name);
+        let single = render_on(&scene, 1);
+        assert_same(&single, &render_on(&scene, 4));
+        assert_same(&single, &render(&scene, &scene.settings));
+    }
+}
+
+#[test]
+fn same_image_for_every_run() {
+    for name in ["cornell_box.toml", "textures.toml"] {
+        // loaded again too, as textures draw their noise from the seed
+        let (first, second) = (load(name), load(name));
+        assert_same(
+            &render(&first, &first.settings),
+            &render(&second, &second.settings),
+        );
+    }
+}